@@ -1,49 +1,81 @@
 // imports
-use anyhow::anyhow;
-use anyhow::Context;
 use clap::{crate_authors, crate_description, crate_name, crate_version};
-use std::io::BufRead;
+use signal_backup_decode::error::Context;
+use std::convert::TryInto;
+use zeroize::Zeroizing;
 
 /// Config struct
 ///
 /// Stores all global variables
 pub struct Config {
-	/// Path to input file
+	/// Path to input file, `-` for stdin
 	pub path_input: std::path::PathBuf,
-	/// Path to output directory. If not given is automatically determined from input path.
-	pub path_output: std::path::PathBuf,
-	/// Password to open backup file
-	pub password: Vec<u8>,
+	/// Password or key file to open backup file, `None` if the password is recovered
+	pub secret: Option<signal_backup_decode::key::Secret>,
+	/// Pattern of a partially known password to recover
+	pub password_pattern: Option<signal_backup_decode::password::Pattern>,
+	/// Only check the password with the first frame?
+	pub check_password: bool,
+	/// Path to save the derived key to, `-` for stdout
+	pub path_export_key: Option<std::path::PathBuf>,
+	/// Password of the backup written with output type BACKUP, if it differs from `password`
+	pub new_password: Option<Zeroizing<Vec<u8>>>,
 	/// Should HMAC be verified?
 	pub verify_mac: bool,
+	/// Skip corrupted frames instead of aborting?
+	pub recover: bool,
+	/// Finish output of truncated backups?
+	pub allow_truncated: bool,
+	/// Upper bounds for frame and attachment lengths
+	pub input_limits: signal_backup_decode::input::Limits,
 	/// Log / verbosity level
 	pub log_level: log::LevelFilter,
 	/// Overwrite existing output files?
 	pub force_overwrite: bool,
-	/// Output type
-	pub output_type: crate::output::SignalOutputType,
+	/// Outputs written in a single pass
+	pub outputs: Vec<Output>,
 	/// Use in memory sqlite database
 	pub output_raw_db_in_memory: bool,
+	/// Format of preference files
+	pub output_raw_preference_format: signal_backup_decode::output_raw::PreferenceFormat,
+	/// Write key value pairs to a JSON file too
+	pub output_raw_key_value_json: bool,
+	/// Store media files once under their hash in this directory
+	pub path_store: Option<std::path::PathBuf>,
+	/// Messages to write, `None` if all messages are written
+	pub filter: Option<signal_backup_decode::output_filter::Filter>,
+}
+
+/// Output to write
+pub struct Output {
+	/// Output type
+	pub output_type: signal_backup_decode::output::SignalOutputType,
+	/// Path to output directory (output file for output types BACKUP and JSONL). If not given is
+	/// automatically determined from input path.
+	pub path: std::path::PathBuf,
 }
 
 impl Config {
 	/// Create new config object
-	pub fn new() -> Result<Self, anyhow::Error> {
+	pub fn new() -> Result<Self, signal_backup_decode::error::Error> {
+		Self::from_args(std::env::args_os())
+	}
+
+	/// Create config object from the command line `args`, starting with the program name
+	pub fn from_args<I, T>(args: I) -> Result<Self, signal_backup_decode::error::Error>
+	where
+		I: IntoIterator<Item = T>,
+		T: Into<std::ffi::OsString> + Clone,
+	{
 		let matches = clap::App::new(crate_name!())
 			.version(crate_version!())
 			.about(crate_description!())
 			.author(crate_authors!())
-			.arg(
-				clap::Arg::with_name("input-file")
-					.help("Sets the input file to use")
-					.takes_value(true)
-					.value_name("INPUT")
-					.required(true)
-					.index(1),
-			)
+			.setting(clap::AppSettings::SubcommandsNegateReqs)
+			.arg(input_arg())
 			.arg(
 				clap::Arg::with_name("output-path")
-					.help("Directory to save output to. If not given, input file directory is used")
+					.help("Directory to save output to (file for output types BACKUP and JSONL). If not given, input file directory is used (stdout for JSONL)")
 					.long("output-path")
 					.short("o")
 					.takes_value(true)
@@ -51,10 +83,13 @@ impl Config {
 			)
 			.arg(
 				clap::Arg::with_name("output-type")
-					.help("Output type, either RAW, CSV or NONE")
+					.help("Output type, either RAW, CSV, BACKUP, JSONL or NONE. Several types can be given as a list or by repeating this option, TYPE=PATH writes an output to PATH instead of output-path")
 					.long("output-type")
 					.short("t")
 					.takes_value(true)
+					.multiple(true)
+					.number_of_values(1)
+					.use_delimiter(true)
 					.value_name("TYPE"),
 			)
 			.arg(
@@ -63,7 +98,8 @@ impl Config {
 					.long("verbosity")
 					.short("v")
 					.takes_value(true)
-					.value_name("LEVEL"),
+					.value_name("LEVEL")
+					.global(true),
 			)
 			.arg(
 				clap::Arg::with_name("force-overwrite")
@@ -76,95 +112,256 @@ impl Config {
 					.help("Do not verify the HMAC of each frame in the backup")
 					.long("no-verify-mac"),
 			)
+			.arg(
+				clap::Arg::with_name("recover")
+					.help(
+						"Skip corrupted frames and continue with the next valid frame. It is \
+						 searched byte by byte, which takes about a second per 5 MB of damaged data.",
+					)
+					.long("recover"),
+			)
+			.arg(
+				clap::Arg::with_name("allow-truncated")
+					.help("Keep the output of backups which are cut off before their end")
+					.long("allow-truncated"),
+			)
+			.arg(
+				clap::Arg::with_name("max-frame-length")
+					.help("Reject frames longer than BYTES (suffixes K, M and G allowed) [default: 64M]")
+					.long("max-frame-length")
+					.takes_value(true)
+					.value_name("BYTES"),
+			)
+			.arg(
+				clap::Arg::with_name("max-attachment-length")
+					.help("Reject attachments, avatars and stickers longer than BYTES (suffixes K, M and G allowed) [default: 2G]")
+					.long("max-attachment-length")
+					.takes_value(true)
+					.value_name("BYTES"),
+			)
 			.arg(
 				clap::Arg::with_name("no-in-memory-db")
 					.help("Do not use in memory sqlite database. Database is immediately created on disk (only considered with output type RAW).")
 					.long("no-in-memory-db"),
 			)
 			.arg(
-				clap::Arg::with_name("password-string")
-					.help("Backup password (30 digits, with or without spaces)")
-					.long("password")
+				clap::Arg::with_name("key-value-json")
+					.help("Also write the key value pairs to key_value.json (only considered with output type RAW)")
+					.long("key-value-json"),
+			)
+			.arg(
+				clap::Arg::with_name("preference-format")
+					.help("Format of preference files, either XML, INI or JSON (only considered with output type RAW) [default: XML]")
+					.long("preference-format")
 					.takes_value(true)
-					.value_name("PASSWORD")
-					.short("p"),
+					.value_name("FORMAT"),
 			)
 			.arg(
-				clap::Arg::with_name("password-file")
-					.help("File to read the backup password from")
-					.long("password-file")
+				clap::Arg::with_name("store")
+					.help("Store attachments, stickers and avatars once under their SHA-256 hash in DIR and link them into the output directory (only considered with output type RAW)")
+					.long("store")
 					.takes_value(true)
-					.value_name("FILE"),
+					.value_name("DIR"),
 			)
 			.arg(
-				clap::Arg::with_name("password-command")
-					.help("Read backup password from stdout from COMMAND")
-					.long("password-command")
+				clap::Arg::with_name("thread")
+					.help("Only write messages of thread ID, can be given several times")
+					.long("thread")
 					.takes_value(true)
-					.value_name("COMMAND"),
+					.multiple(true)
+					.number_of_values(1)
+					.value_name("ID"),
+			)
+			.arg(
+				clap::Arg::with_name("recipient")
+					.help("Only write messages sent from or to RECIPIENT (recipient id or phone number of old backups), can be given several times")
+					.long("recipient")
+					.takes_value(true)
+					.multiple(true)
+					.number_of_values(1)
+					.value_name("RECIPIENT"),
+			)
+			.arg(
+				clap::Arg::with_name("since")
+					.help("Only write messages sent on or after DATE (YYYY-MM-DD, UTC)")
+					.long("since")
+					.takes_value(true)
+					.value_name("DATE"),
+			)
+			.arg(
+				clap::Arg::with_name("before")
+					.help("Only write messages sent before DATE (YYYY-MM-DD, UTC)")
+					.long("before")
+					.takes_value(true)
+					.value_name("DATE"),
+			)
+			.args(&password_args())
+			.arg(
+				clap::Arg::with_name("password-pattern")
+					.help("Recover a partially known password and exit. Unknown digits are given as ?, groups of candidates as (12345|12354)")
+					.long("password-pattern")
+					.takes_value(true)
+					.value_name("PATTERN"),
+			)
+			.arg(
+				clap::Arg::with_name("export-key")
+					.help("Save the key derived from the backup password to FILE (- for stdout) and exit")
+					.long("export-key")
+					.takes_value(true)
+					.value_name("FILE"),
 			)
 			.group(
 				clap::ArgGroup::with_name("password")
-					.args(&["password-string", "password-file", "password-command"])
+					.args(&[
+						"password-string",
+						"password-file",
+						"password-command",
+						"key-file",
+						"password-pattern",
+					])
 					.required(true)
 					.multiple(false),
 			)
-			.get_matches();
+			.arg(
+				clap::Arg::with_name("new-password-string")
+					.help("Password of the new backup (only considered with output type BACKUP)")
+					.long("new-password")
+					.takes_value(true)
+					.value_name("PASSWORD"),
+			)
+			.arg(
+				clap::Arg::with_name("new-password-file")
+					.help("File to read the password of the new backup from")
+					.long("new-password-file")
+					.takes_value(true)
+					.value_name("FILE"),
+			)
+			.arg(
+				clap::Arg::with_name("new-password-command")
+					.help("Read password of the new backup from stdout from COMMAND")
+					.long("new-password-command")
+					.takes_value(true)
+					.value_name("COMMAND"),
+			)
+			.group(
+				clap::ArgGroup::with_name("new-password")
+					.args(&[
+						"new-password-string",
+						"new-password-file",
+						"new-password-command",
+					])
+					.multiple(false),
+			)
+			.subcommand(
+				clap::SubCommand::with_name("check-password")
+					.about("Check the backup password by decrypting only the first frame. Exits with code 2 if the password is wrong.")
+					.arg(input_arg())
+					.args(&password_args())
+					.group(
+						clap::ArgGroup::with_name("password")
+							.args(&[
+								"password-string",
+								"password-file",
+								"password-command",
+								"key-file",
+							])
+							.required(true)
+							.multiple(false),
+					),
+			)
+			.get_matches_from(args);
+		let (matches, check_password) = match matches.subcommand() {
+			("check-password", Some(x)) => (x, true),
+			_ => (&matches, false),
+		};
 
 		// input file handling
 		let input_file = std::path::PathBuf::from(matches.value_of("input-file").unwrap());
 
-		// output path handling
-		let output_path = std::path::PathBuf::from(matches.value_of("output-path").unwrap_or({
-			input_file
-				.file_stem()
-				.unwrap()
-				.to_str()
-				.context("output-path is not given and path to input file could not be read.")?
-		}));
+		// determine output types and paths
+		let mut outputs = Vec::new();
+		for x in matches
+			.values_of("output-type")
+			.map_or_else(|| vec!["raw"], |x| x.collect())
+		{
+			let (output_type, path) = match x.find('=') {
+				Some(i) => (&x[..i], Some(&x[i + 1..])),
+				None => (x, None),
+			};
+			let output_type = parse_output_type(output_type)?;
+			let path = output_path(
+				path.or_else(|| matches.value_of("output-path")),
+				&output_type,
+				&input_file,
+				check_password,
+			)?;
+			outputs.push(Output { output_type, path });
+		}
 
-		// password handling
-		let mut password = {
-			if matches.is_present("password-string") {
-				String::from(matches.value_of("password-string").unwrap())
-			} else if matches.is_present("password-file") {
-				let password_file = std::io::BufReader::new(
-					std::fs::File::open(matches.value_of("password-file").unwrap())
-						.context("Unable to open password file")?,
-				);
-				password_file
-					.lines()
-					.next()
-					.context("Password file is empty")?
-					.context("Unable to read from password file")?
-			} else if matches.is_present("password-command") {
-				let shell = std::env::var("SHELL").context("Could not determine current shell")?;
-				let output = std::process::Command::new(shell)
-					.arg("-c")
-					.arg(matches.value_of("password-command").unwrap())
-					.output()
-					.context("Failed to execute password command")?;
-
-				// check whether command returned an error code
-				if output.status.success() {
-					String::from_utf8(output.stdout)
-						.context("Password command returned invalid characters")?
-						.lines()
-						.next()
-						.context("Password command returned empty line")?
-						.into()
-				} else {
-					return Err(anyhow!("Password command returned error code"));
+		// outputs written to files cannot share their path with other outputs
+		for (i, a) in outputs.iter().enumerate() {
+			for b in outputs[i + 1..].iter() {
+				if !check_password
+					&& a.path == b.path
+					&& (is_file_output(&a.output_type) || is_file_output(&b.output_type))
+				{
+					return Err(signal_backup_decode::error::Error::Config(format!(
+						"output types {} and {} cannot both be written to {}",
+						a.output_type,
+						b.output_type,
+						a.path.to_string_lossy()
+					)));
 				}
-			} else {
-				unreachable!()
 			}
+		}
+		let output_backup = outputs.iter().any(|x| {
+			matches!(
+				x.output_type,
+				signal_backup_decode::output::SignalOutputType::Backup
+			)
+		});
+
+		// password handling
+		let password_pattern = matches
+			.value_of("password-pattern")
+			.map(signal_backup_decode::password::Pattern::parse)
+			.transpose()?;
+		if password_pattern.is_some() && matches.is_present("export-key") {
+			return Err(signal_backup_decode::error::Error::Config(
+				"a key can only be exported with a known password".to_string(),
+			));
+		}
+		let secret = if let Some(x) = matches.value_of("key-file") {
+			Some(signal_backup_decode::key::Secret::Key(
+				signal_backup_decode::key::BackupKey::read(std::path::Path::new(x))?,
+			))
+		} else {
+			read_password(
+				matches,
+				"password-string",
+				"password-file",
+				"password-command",
+			)?
+			.map(signal_backup_decode::key::Secret::Password)
 		};
-		password.retain(|c| c >= '0' && c <= '9');
-		let password = password.as_bytes().to_vec();
-		if password.len() != 30 {
-			return Err(anyhow!(
-				"Wrong password length (30 numeric characters are expected)"
+		let new_password = read_password(
+			matches,
+			"new-password-string",
+			"new-password-file",
+			"new-password-command",
+		)
+		.context("Invalid new password")?;
+		if new_password.is_some() && !output_backup {
+			return Err(signal_backup_decode::error::Error::Config(
+				"a new password can only be given for output type BACKUP".to_string(),
+			));
+		}
+		if let (true, Some(signal_backup_decode::key::Secret::Key(_)), None) =
+			(output_backup, &secret, &new_password)
+		{
+			return Err(signal_backup_decode::error::Error::Config(
+				"a new password has to be given for output type BACKUP when using a key file"
+					.to_string(),
 			));
 		}
 
@@ -175,33 +372,281 @@ impl Config {
 				"info" => log::LevelFilter::Info,
 				"warn" => log::LevelFilter::Warn,
 				"error" => log::LevelFilter::Error,
-				_ => return Err(anyhow!("Unknown log level given")),
+				_ => {
+					return Err(signal_backup_decode::error::Error::Config(
+						"Unknown log level given".to_string(),
+					))
+				}
 			}
 		} else {
 			log::LevelFilter::Info
 		};
 
-		// determine output type
-		let output_type = if let Some(x) = matches.value_of("output-type") {
-			match x.to_lowercase().as_str() {
-				"none" => crate::output::SignalOutputType::None,
-				"raw" => crate::output::SignalOutputType::Raw,
-				"csv" => crate::output::SignalOutputType::Csv,
-				_ => return Err(anyhow!("Unknown output type given")),
-			}
-		} else {
-			crate::output::SignalOutputType::Raw
+		let preference_format = match matches.value_of("preference-format") {
+			Some(x) => match x.to_lowercase().as_str() {
+				"xml" => signal_backup_decode::output_raw::PreferenceFormat::Xml,
+				"ini" => signal_backup_decode::output_raw::PreferenceFormat::Ini,
+				"json" => signal_backup_decode::output_raw::PreferenceFormat::Json,
+				_ => {
+					return Err(signal_backup_decode::error::Error::Config(format!(
+						"Unknown preference format given: {}",
+						x
+					)))
+				}
+			},
+			None => signal_backup_decode::output_raw::PreferenceFormat::Xml,
+		};
+
+		// length limits handling
+		let mut input_limits = signal_backup_decode::input::Limits::default();
+		if let Some(x) = matches.value_of("max-frame-length") {
+			input_limits.frame_length = parse_bytes(x)
+				.and_then(|x| {
+					x.try_into().map_err(|_| {
+						signal_backup_decode::error::Error::Config(format!(
+							"{} bytes exceed the address space",
+							x
+						))
+					})
+				})
+				.context("Invalid maximum frame length given")?;
+		}
+		if let Some(x) = matches.value_of("max-attachment-length") {
+			input_limits.attachment_length =
+				parse_bytes(x).context("Invalid maximum attachment length given")?;
+		}
+
+		// filter handling
+		let filter = signal_backup_decode::output_filter::Filter {
+			threads: matches
+				.values_of("thread")
+				.map_or_else(Vec::new, |x| x.collect())
+				.into_iter()
+				.map(|x| {
+					x.parse().map_err(|_| {
+						signal_backup_decode::error::Error::Config(format!(
+							"Invalid thread id given: {}",
+							x
+						))
+					})
+				})
+				.collect::<Result<_, _>>()?,
+			recipients: matches
+				.values_of("recipient")
+				.map_or_else(Vec::new, |x| x.map(String::from).collect()),
+			since: matches.value_of("since").map(parse_date).transpose()?,
+			before: matches.value_of("before").map(parse_date).transpose()?,
 		};
 
 		Ok(Self {
 			path_input: input_file,
-			path_output: output_path,
-			password,
+			secret,
+			password_pattern,
+			check_password,
+			path_export_key: matches.value_of("export-key").map(std::path::PathBuf::from),
+			new_password,
 			verify_mac: !matches.is_present("no_verify_mac"),
+			recover: matches.is_present("recover"),
+			allow_truncated: matches.is_present("allow-truncated"),
+			input_limits,
 			log_level,
 			force_overwrite: matches.is_present("force-overwrite"),
-			output_type,
+			outputs,
 			output_raw_db_in_memory: !matches.is_present("no-in-memory-db"),
+			output_raw_preference_format: preference_format,
+			output_raw_key_value_json: matches.is_present("key-value-json"),
+			path_store: matches.value_of("store").map(std::path::PathBuf::from),
+			filter: if filter.is_empty() {
+				None
+			} else {
+				Some(filter)
+			},
 		})
 	}
 }
+
+/// Parse the name of an output type
+fn parse_output_type(
+	name: &str,
+) -> Result<signal_backup_decode::output::SignalOutputType, signal_backup_decode::error::Error> {
+	match name.to_lowercase().as_str() {
+		"none" => Ok(signal_backup_decode::output::SignalOutputType::None),
+		"raw" => Ok(signal_backup_decode::output::SignalOutputType::Raw),
+		"csv" => Ok(signal_backup_decode::output::SignalOutputType::Csv),
+		"backup" => Ok(signal_backup_decode::output::SignalOutputType::Backup),
+		"jsonl" => Ok(signal_backup_decode::output::SignalOutputType::Jsonl),
+		_ => Err(signal_backup_decode::error::Error::Config(format!(
+			"Unknown output type given: {}",
+			name
+		))),
+	}
+}
+
+/// Is output type `output_type` written to a single file instead of a directory?
+fn is_file_output(output_type: &signal_backup_decode::output::SignalOutputType) -> bool {
+	matches!(
+		output_type,
+		signal_backup_decode::output::SignalOutputType::Backup
+			| signal_backup_decode::output::SignalOutputType::Jsonl
+	)
+}
+
+/// Determine the path of an output of type `output_type`
+///
+/// `path` is the path given for the output, if it is not given the path is derived from
+/// `input_file`.
+fn output_path(
+	path: Option<&str>,
+	output_type: &signal_backup_decode::output::SignalOutputType,
+	input_file: &std::path::Path,
+	check_password: bool,
+) -> Result<std::path::PathBuf, signal_backup_decode::error::Error> {
+	if let Some(x) = path {
+		Ok(std::path::PathBuf::from(x))
+	} else if check_password {
+		// nothing is written
+		Ok(std::path::PathBuf::new())
+	} else if let signal_backup_decode::output::SignalOutputType::Jsonl = output_type {
+		Ok(std::path::PathBuf::from("-"))
+	} else if input_file == std::path::Path::new("-") {
+		Err(signal_backup_decode::error::Error::Config(
+			"output-path has to be given when reading from stdin".to_string(),
+		))
+	} else if let signal_backup_decode::output::SignalOutputType::Backup = output_type {
+		Err(signal_backup_decode::error::Error::Config(
+			"output-path has to be given for output type BACKUP".to_string(),
+		))
+	} else {
+		Ok(std::path::PathBuf::from(
+			input_file.file_stem().unwrap().to_str().ok_or_else(|| {
+				signal_backup_decode::error::Error::Config(
+					"output-path is not given and path to input file could not be read."
+						.to_string(),
+				)
+			})?,
+		))
+	}
+}
+
+/// Positional argument of the input file
+fn input_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+	clap::Arg::with_name("input-file")
+		.help("Sets the input file to use, - reads from stdin")
+		.takes_value(true)
+		.value_name("INPUT")
+		.required(true)
+		.index(1)
+}
+
+/// Arguments giving the password of the backup
+fn password_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+	vec![
+		clap::Arg::with_name("password-string")
+			.help("Backup password (30 digits, with or without spaces)")
+			.long("password")
+			.takes_value(true)
+			.value_name("PASSWORD")
+			.short("p"),
+		clap::Arg::with_name("password-file")
+			.help("File to read the backup password from")
+			.long("password-file")
+			.takes_value(true)
+			.value_name("FILE"),
+		clap::Arg::with_name("password-command")
+			.help("Read backup password from stdout from COMMAND")
+			.long("password-command")
+			.takes_value(true)
+			.value_name("COMMAND"),
+		clap::Arg::with_name("key-file")
+			.help("Key file written by --export-key, used instead of the backup password")
+			.long("key-file")
+			.takes_value(true)
+			.value_name("FILE"),
+	]
+}
+
+/// Parse a number of bytes with an optional binary suffix, e.g. `64M`
+fn parse_bytes(value: &str) -> Result<u64, signal_backup_decode::error::Error> {
+	let value = value.trim();
+	let (number, factor) = match value.chars().last().map(|x| x.to_ascii_uppercase()) {
+		Some('K') => (&value[..value.len() - 1], 1 << 10),
+		Some('M') => (&value[..value.len() - 1], 1 << 20),
+		Some('G') => (&value[..value.len() - 1], 1 << 30),
+		_ => (value, 1),
+	};
+
+	number
+		.parse::<u64>()
+		.ok()
+		.and_then(|x| x.checked_mul(factor))
+		.ok_or_else(|| {
+			signal_backup_decode::error::Error::Config(format!(
+				"cannot parse number of bytes: {}",
+				value
+			))
+		})
+}
+
+/// Parse a date (`YYYY-MM-DD`) to milliseconds since the epoch at its start in UTC
+fn parse_date(value: &str) -> Result<i64, signal_backup_decode::error::Error> {
+	chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+		.map(|x| x.and_hms(0, 0, 0).timestamp_millis())
+		.map_err(|_| {
+			signal_backup_decode::error::Error::Config(format!(
+				"cannot parse date (YYYY-MM-DD expected): {}",
+				value
+			))
+		})
+}
+
+/// Read password given by one of the arguments `string`, `file` or `command`
+///
+/// Returns `None` if none of the arguments is present. All buffers containing the password are
+/// wiped when they are dropped, except for a password given as `string` argument, which stays
+/// unwiped in `matches`.
+fn read_password(
+	matches: &clap::ArgMatches,
+	string: &str,
+	file: &str,
+	command: &str,
+) -> Result<Option<Zeroizing<Vec<u8>>>, signal_backup_decode::error::Error> {
+	let input = if let Some(x) = matches.value_of(string) {
+		Zeroizing::new(String::from(x))
+	} else if let Some(x) = matches.value_of(file) {
+		// read the whole file at once, buffered reading would leave copies behind
+		Zeroizing::new(std::fs::read_to_string(x).context("Unable to read password file")?)
+	} else if let Some(x) = matches.value_of(command) {
+		let shell = std::env::var("SHELL").map_err(|_| {
+			signal_backup_decode::error::Error::Config(
+				"Could not determine current shell".to_string(),
+			)
+		})?;
+		let output = std::process::Command::new(shell)
+			.arg("-c")
+			.arg(x)
+			.output()
+			.context("Failed to execute password command")?;
+		let stdout = Zeroizing::new(output.stdout);
+
+		// check whether command returned an error code
+		if output.status.success() {
+			Zeroizing::new(String::from_utf8(stdout.to_vec()).map_err(|_| {
+				signal_backup_decode::error::Error::Config(
+					"Password command returned invalid characters".to_string(),
+				)
+			})?)
+		} else {
+			return Err(signal_backup_decode::error::Error::Config(
+				"Password command returned error code".to_string(),
+			));
+		}
+	} else {
+		return Ok(None);
+	};
+
+	// only the first line is considered
+	let line = input.lines().next().ok_or_else(|| {
+		signal_backup_decode::error::Error::Config("Password is empty".to_string())
+	})?;
+	Ok(Some(signal_backup_decode::password::parse(line)?))
+}
@@ -0,0 +1,195 @@
+//! AES-256-CTR backends
+//!
+//! The backend is selected with cargo features: `rust-crypto` uses the pure-Rust `aes` crate,
+//! otherwise `openssl` is used.
+
+#[cfg(not(any(feature = "openssl", feature = "rust-crypto")))]
+compile_error!("either feature \"openssl\" or \"rust-crypto\" has to be enabled");
+
+/// AES-256-CTR stream started with a key and iv
+///
+/// The cipher context is kept for the whole backup, every frame restarts the stream with
+/// `set_iv`.
+pub trait StreamCipher: Sized {
+	fn new(key: &[u8], iv: &[u8]) -> Self;
+
+	/// Start a new stream with `iv`, keeping the key
+	fn set_iv(&mut self, iv: &[u8]);
+
+	/// En- or decrypt `data` in place, continuing the stream
+	fn apply_keystream(&mut self, data: &mut [u8]);
+}
+
+/// Stream cipher of the selected backend
+#[cfg(feature = "rust-crypto")]
+pub type Aes256Ctr = rust_crypto_backend::Aes256Ctr;
+#[cfg(not(feature = "rust-crypto"))]
+pub type Aes256Ctr = openssl_backend::Aes256Ctr;
+
+/// Fill `data` with cryptographically secure random bytes
+#[cfg(feature = "rust-crypto")]
+pub fn random_bytes(data: &mut [u8]) -> Result<(), crate::error::Error> {
+	getrandom::getrandom(data).map_err(|e| std::io::Error::other(e.to_string()).into())
+}
+
+/// Fill `data` with cryptographically secure random bytes
+#[cfg(not(feature = "rust-crypto"))]
+pub fn random_bytes(data: &mut [u8]) -> Result<(), crate::error::Error> {
+	openssl::rand::rand_bytes(data).map_err(std::io::Error::from)?;
+	Ok(())
+}
+
+// only needed for cross-backend tests if both backends are enabled
+#[cfg(all(feature = "openssl", any(not(feature = "rust-crypto"), test)))]
+pub mod openssl_backend {
+	/// AES-256-CTR of OpenSSL
+	///
+	/// OpenSSL wipes the key schedule itself when the context is freed.
+	pub struct Aes256Ctr(openssl::cipher_ctx::CipherCtx);
+
+	impl super::StreamCipher for Aes256Ctr {
+		fn new(key: &[u8], iv: &[u8]) -> Self {
+			let mut ctx = openssl::cipher_ctx::CipherCtx::new().unwrap();
+			ctx.encrypt_init(
+				Some(openssl::cipher::Cipher::aes_256_ctr()),
+				Some(key),
+				Some(iv),
+			)
+			.unwrap();
+			Self(ctx)
+		}
+
+		fn set_iv(&mut self, iv: &[u8]) {
+			self.0.encrypt_init(None, None, Some(iv)).unwrap();
+		}
+
+		fn apply_keystream(&mut self, data: &mut [u8]) {
+			// block size of CTR is 1, so no additional space is needed
+			self.0.cipher_update_inplace(data, data.len()).unwrap();
+		}
+	}
+}
+
+#[cfg(feature = "rust-crypto")]
+pub mod rust_crypto_backend {
+	use aes::{BlockCipher, NewBlockCipher};
+
+	/// Number of key stream blocks computed at once
+	const COUNT_BLOCK: usize = 8;
+
+	/// AES-256-CTR with a 128 bit counter, like OpenSSL and Signal use it
+	///
+	/// `ctr::Ctr128` only increases the lower 64 bits of the iv, so the counter mode is
+	/// implemented here.
+	pub struct Aes256Ctr {
+		cipher: aes::Aes256,
+		counter: u128,
+		keystream: Vec<aes::cipher::block::Block<aes::Aes256>>,
+		position: usize,
+	}
+
+	impl Aes256Ctr {
+		/// Compute the next blocks of the key stream
+		fn next_keystream(&mut self) {
+			for block in self.keystream.iter_mut() {
+				block.copy_from_slice(&self.counter.to_be_bytes());
+				self.counter = self.counter.wrapping_add(1);
+			}
+			self.cipher.encrypt_slice(&mut self.keystream);
+			self.position = 0;
+		}
+	}
+
+	// the key schedule of `aes` 0.6 cannot be wiped, only the computed key stream is
+	impl Drop for Aes256Ctr {
+		fn drop(&mut self) {
+			for block in self.keystream.iter_mut() {
+				zeroize::Zeroize::zeroize(block.as_mut_slice());
+			}
+		}
+	}
+
+	impl super::StreamCipher for Aes256Ctr {
+		fn new(key: &[u8], iv: &[u8]) -> Self {
+			let mut cipher = Self {
+				cipher: aes::Aes256::new_varkey(key).unwrap(),
+				counter: 0,
+				keystream: vec![Default::default(); COUNT_BLOCK],
+				position: 0,
+			};
+			cipher.set_iv(iv);
+			cipher
+		}
+
+		fn set_iv(&mut self, iv: &[u8]) {
+			let mut counter = [0u8; 16];
+			counter.copy_from_slice(iv);
+			self.counter = u128::from_be_bytes(counter);
+			self.position = COUNT_BLOCK * 16;
+		}
+
+		fn apply_keystream(&mut self, data: &mut [u8]) {
+			let mut data = data;
+			while !data.is_empty() {
+				if self.position == COUNT_BLOCK * 16 {
+					self.next_keystream();
+				}
+
+				// use the rest of the current block
+				let block = &self.keystream[self.position / 16][self.position % 16..];
+				let count = std::cmp::min(block.len(), data.len());
+				for (x, k) in data[..count].iter_mut().zip(block) {
+					*x ^= k;
+				}
+				self.position += count;
+				data = &mut std::mem::take(&mut data)[count..];
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn random() {
+		let mut data = [0u8; 32];
+		random_bytes(&mut data).unwrap();
+		assert_ne!(data, [0u8; 32]);
+	}
+
+	#[cfg(all(feature = "openssl", feature = "rust-crypto"))]
+	#[test]
+	fn same_output() {
+		let key: Vec<u8> = (0..32).collect();
+		// the counter overflows within the stream
+		let iv = [0xff; 16];
+		let data: Vec<u8> = (0..1000).map(|x| x as u8).collect();
+
+		let mut a = openssl_backend::Aes256Ctr::new(&key, &iv);
+		let mut b = rust_crypto_backend::Aes256Ctr::new(&key, &iv);
+		for chunk in [1, 15, 16, 17, 100, 851].iter() {
+			let mut data_a = data[..*chunk].to_vec();
+			let mut data_b = data[..*chunk].to_vec();
+			a.apply_keystream(&mut data_a);
+			b.apply_keystream(&mut data_b);
+			assert_eq!(data_a, data_b);
+		}
+
+		// restart the stream
+		let iv = [0x01; 16];
+		a.set_iv(&iv);
+		b.set_iv(&iv);
+		let mut data_a = data.clone();
+		let mut data_b = data.clone();
+		a.apply_keystream(&mut data_a);
+		b.apply_keystream(&mut data_b);
+		assert_eq!(data_a, data_b);
+		assert_eq!(data_a, {
+			let mut x = data;
+			openssl_backend::Aes256Ctr::new(&key, &iv).apply_keystream(&mut x);
+			x
+		});
+	}
+}
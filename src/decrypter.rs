@@ -1,65 +1,95 @@
+use crate::cipher::StreamCipher;
+use byteorder::ByteOrder;
 use hmac::crypto_mac::Mac;
 use hmac::crypto_mac::NewMac;
-use openssl;
 use sha2::Digest;
 use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, Zeroizing};
 
 /// Used length of HMAC in bytes
 pub const LENGTH_HMAC: usize = 10;
 
+/// Derive cipher key and MAC key from backup password and salt
+///
+/// The same keys are used to encrypt and decrypt a backup. Intermediate hashes are wiped when
+/// they are dropped.
+pub fn derive_keys(password: &[u8], salt: &[u8]) -> (Zeroizing<Vec<u8>>, Zeroizing<Vec<u8>>) {
+	// create hash
+	// reserve space for the whole hash, so the buffer is never reallocated
+	let mut hash = Zeroizing::new(Vec::with_capacity(64));
+	hash.extend_from_slice(password);
+	let mut hasher = sha2::Sha512::new();
+	hasher.update(salt);
+
+	for _ in 0..250000 {
+		hasher.update(&*hash);
+		hasher.update(password);
+		// overwrite the hash in place instead of leaving copies behind
+		let mut result = hasher.finalize_reset();
+		hash.clear();
+		hash.extend_from_slice(&result);
+		result.as_mut_slice().zeroize();
+	}
+
+	// create secrets
+	let info = b"Backup Export";
+	let mut okm = Zeroizing::new([0u8; 64]);
+	let hk = hkdf::Hkdf::<sha2::Sha256>::new(None, &hash[..32]);
+	hk.expand(info, &mut okm[..]).unwrap();
+
+	(
+		Zeroizing::new(okm[..32].to_vec()),
+		Zeroizing::new(okm[32..].to_vec()),
+	)
+}
+
+/// Increase the counter in the first four bytes of `iv`
+pub fn increase_counter(iv: &mut [u8]) {
+	for v in iv.iter_mut().take(4).rev() {
+		if *v < u8::MAX {
+			*v += 1;
+			break;
+		} else {
+			*v = 0;
+		}
+	}
+}
+
 /// Decrypt bytes
+///
+/// All data decrypted between two calls of `increase_iv` is part of the same AES-CTR stream.
+/// This is needed for backups with encrypted frame lengths, where the frame data continues the
+/// stream used to decrypt the frame length. The cipher context is created once and only
+/// restarted with the new iv for every frame.
 pub struct Decrypter {
 	mac: Option<hmac::Hmac<sha2::Sha256>>,
-	key: Vec<u8>,
 	iv: Vec<u8>,
+	cipher: crate::cipher::Aes256Ctr,
 }
 
 impl Decrypter {
-	pub fn new(key: &[u8], salt: &[u8], iv: &[u8], verify_mac: bool) -> Self {
-		// create hash
-		let mut hash = key.to_vec();
-		let mut hasher = sha2::Sha512::new();
-		hasher.update(&salt);
-
-		for _ in 0..250000 {
-			hasher.update(&hash);
-			hasher.update(key);
-			hash = hasher.finalize_reset().to_vec();
-		}
-
-		// create secrets
-		let info = b"Backup Export";
-		let mut okm = [0u8; 64];
-		let hk = hkdf::Hkdf::<sha2::Sha256>::new(None, &hash[..32]);
-		hk.expand(info, &mut okm).unwrap();
-
+	pub fn new(key: &crate::key::BackupKey, iv: &[u8], verify_mac: bool) -> Self {
 		// create hmac and cipher
 		Self {
 			mac: if verify_mac {
-				Some(hmac::Hmac::<sha2::Sha256>::new_varkey(&okm[32..]).unwrap())
+				Some(hmac::Hmac::<sha2::Sha256>::new_varkey(&key.mac_key).unwrap())
 			} else {
 				None
 			},
-			key: okm[..32].to_vec(),
 			iv: iv.to_vec(),
+			cipher: crate::cipher::Aes256Ctr::new(&key.cipher_key, iv),
 		}
 	}
 
-	pub fn decrypt(&mut self, data_encrypted: &[u8]) -> Vec<u8> {
+	/// Decrypt `data` in place and add the encrypted data to the MAC
+	pub fn decrypt(&mut self, data: &mut [u8]) {
 		// check hmac?
 		if let Some(ref mut hmac) = self.mac {
 			// calculate hmac of frame data
-			hmac.update(&data_encrypted);
+			hmac.update(data);
 		}
 
-		// decrypt
-		openssl::symm::decrypt(
-			openssl::symm::Cipher::aes_256_ctr(),
-			&self.key,
-			Some(&self.iv),
-			data_encrypted,
-		)
-		.unwrap()
+		self.cipher.apply_keystream(data);
 	}
 
 	pub fn mac_update_with_iv(&mut self) {
@@ -87,16 +117,42 @@ impl Decrypter {
 		Ok(())
 	}
 
-	// TODO what is happening here?
-	pub fn increase_iv(&mut self) {
-		for v in self.iv.iter_mut().take(4).rev() {
-			if *v < std::u8::MAX {
-				*v += 1;
-				break;
-			} else {
-				*v = 0;
-			}
+	/// Reset MAC and cipher stream, e.g. after an error in the middle of a frame
+	pub fn reset(&mut self) {
+		if let Some(ref mut hmac) = self.mac {
+			hmac.reset();
 		}
+		self.cipher.set_iv(&self.iv);
+	}
+
+	/// Counter part of the iv, which is increased for every frame and attachment
+	pub fn get_counter(&self) -> u32 {
+		byteorder::BigEndian::read_u32(&self.iv[..4])
+	}
+
+	/// Set counter part of the iv and start a new stream
+	pub fn set_counter(&mut self, counter: u32) {
+		byteorder::BigEndian::write_u32(&mut self.iv[..4], counter);
+		self.reset();
+	}
+
+	/// Return the first `length` bytes of the key stream used for the given `counter`
+	///
+	/// The MAC is not touched, but the current stream is restarted.
+	pub fn keystream(&mut self, counter: u32, length: usize) -> Vec<u8> {
+		let mut iv = self.iv.clone();
+		byteorder::BigEndian::write_u32(&mut iv[..4], counter);
+		let mut data = vec![0u8; length];
+		self.cipher.set_iv(&iv);
+		self.cipher.apply_keystream(&mut data);
+		self.cipher.set_iv(&self.iv);
+		data
+	}
+
+	/// Start a new stream with the next counter, used for every frame and attachment
+	pub fn increase_iv(&mut self) {
+		increase_counter(&mut self.iv);
+		self.cipher.set_iv(&self.iv);
 	}
 }
 
@@ -134,8 +190,8 @@ mod tests {
 		// test increase at position 3
 		let mut dec = Decrypter {
 			mac: None,
-			key: key.to_vec(),
 			iv: iv.to_vec(),
+			cipher: crate::cipher::Aes256Ctr::new(&key, &iv),
 		};
 		dec.increase_iv();
 
@@ -147,8 +203,8 @@ mod tests {
 		iv[2] = 255;
 		let mut dec = Decrypter {
 			mac: None,
-			key: key.to_vec(),
 			iv: iv.to_vec(),
+			cipher: crate::cipher::Aes256Ctr::new(&key, &iv),
 		};
 		dec.increase_iv();
 
@@ -157,4 +213,82 @@ mod tests {
 		iv[1] = 1;
 		assert_eq!(dec.iv, iv);
 	}
+
+	#[test]
+	fn decrypt_stream() {
+		let data: Vec<u8> = (0..100).collect();
+		let mut dec = Decrypter {
+			mac: None,
+			iv: vec![2; 16],
+			cipher: crate::cipher::Aes256Ctr::new(&[1; 32], &[2; 16]),
+		};
+		let mut whole = data.clone();
+		dec.decrypt(&mut whole);
+
+		// decrypting in parts continues the stream until the iv is increased
+		dec.reset();
+		let mut parts = data.clone();
+		dec.decrypt(&mut parts[..4]);
+		dec.decrypt(&mut parts[4..]);
+		assert_eq!(whole, parts);
+
+		// a new stream is started after increasing the iv
+		dec.increase_iv();
+		let mut next = data;
+		dec.decrypt(&mut next);
+		assert_ne!(next, whole);
+	}
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+	use super::*;
+
+	/// Number of frames decrypted per iteration
+	const COUNT_FRAME: usize = 1000;
+	/// Length of a frame in bytes, about the size of a statement
+	const LENGTH_FRAME: usize = 100;
+
+	/// Decrypt every frame with a new cipher context and output buffer, like earlier versions
+	#[cfg(feature = "openssl")]
+	#[bench]
+	fn decrypt_new_context(b: &mut test::Bencher) {
+		let key = [1u8; 32];
+		let data = [7u8; LENGTH_FRAME];
+		b.bytes = (COUNT_FRAME * LENGTH_FRAME) as u64;
+		b.iter(|| {
+			let mut iv = vec![2u8; 16];
+			for _ in 0..COUNT_FRAME {
+				let plain = openssl::symm::decrypt(
+					openssl::symm::Cipher::aes_256_ctr(),
+					&key,
+					Some(&iv),
+					&data,
+				)
+				.unwrap();
+				test::black_box(plain);
+				increase_counter(&mut iv);
+			}
+		});
+	}
+
+	/// Decrypt every frame in place with the cipher context kept by `Decrypter`
+	#[bench]
+	fn decrypt_reused_context(b: &mut test::Bencher) {
+		let key = crate::key::BackupKey {
+			cipher_key: Zeroizing::new(vec![1; 32]),
+			mac_key: Zeroizing::new(vec![3; 32]),
+			salt_fingerprint: Vec::new(),
+		};
+		let mut data = [7u8; LENGTH_FRAME];
+		b.bytes = (COUNT_FRAME * LENGTH_FRAME) as u64;
+		b.iter(|| {
+			let mut decrypter = Decrypter::new(&key, &[2; 16], false);
+			for _ in 0..COUNT_FRAME {
+				decrypter.decrypt(&mut data);
+				test::black_box(&data);
+				decrypter.increase_iv();
+			}
+		});
+	}
 }
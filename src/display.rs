@@ -7,10 +7,19 @@ pub struct Progress {
 }
 
 impl Progress {
-	pub fn new(bytes_to_read: u64, frames_to_read: u64, hidden: bool) -> Self {
-		let sty_bytes = indicatif::ProgressStyle::default_bar()
+	/// Create new progress bars
+	///
+	/// If `bytes_to_read` is not known (e.g. when reading from stdin), only the number of read
+	/// bytes is displayed.
+	pub fn new(bytes_to_read: Option<u64>, frames_to_read: u64, hidden: bool) -> Self {
+		let sty_bytes = if bytes_to_read.is_some() {
+			indicatif::ProgressStyle::default_bar()
                     .template("             Bytes read: [{elapsed_precise}] [{bar:50.blue/blue}] {bytes}/{total_bytes}")
-                    .progress_chars("#>-");
+                    .progress_chars("#>-")
+		} else {
+			indicatif::ProgressStyle::default_spinner()
+				.template("             Bytes read: [{elapsed_precise}] {spinner:.blue} {bytes}")
+		};
 		let sty_frames = indicatif::ProgressStyle::default_bar()
                     .template("Read vs. written frames: [{elapsed_precise}] [{bar:50.cyan/cyan}] {pos:>5}/{len:5}")
                     .progress_chars("#>-");
@@ -23,7 +32,10 @@ impl Progress {
 			bar_bytes = None;
 			bar_frames = None;
 		} else {
-			bar_bytes = Some(bar_multi.add(indicatif::ProgressBar::new(bytes_to_read)));
+			bar_bytes = Some(bar_multi.add(match bytes_to_read {
+				Some(x) => indicatif::ProgressBar::new(x),
+				None => indicatif::ProgressBar::new_spinner(),
+			}));
 			bar_bytes.as_ref().unwrap().set_style(sty_bytes);
 			bar_frames = Some(bar_multi.add(indicatif::ProgressBar::new(frames_to_read)));
 			bar_frames.as_ref().unwrap().set_style(sty_frames);
@@ -0,0 +1,319 @@
+use crate::error::Context;
+use std::io::Read;
+use std::io::Write;
+
+/// Length of the salt of new backups in bytes
+const LENGTH_SALT: usize = 32;
+
+/// Length of the iv of new backups in bytes
+const LENGTH_IV: usize = 16;
+
+/// Highest backup version which can be written
+pub const MAX_VERSION: u32 = 1;
+
+/// Size of chunks in which attached data is encrypted
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Write backup file
+///
+/// Frames are encrypted and written the same way `crate::input::InputFile` reads them. The
+/// header is written on creation, the end frame by `finish`.
+pub struct Encoder<W: Write> {
+	writer: W,
+	encrypter: crate::encrypter::Encrypter,
+	version: u32,
+	finished: bool,
+	broken: bool,
+}
+
+impl<W: Write> Encoder<W> {
+	/// Create a backup encrypted with `password`, using a random salt and iv
+	pub fn new(writer: W, password: &[u8], version: u32) -> Result<Self, crate::error::Error> {
+		let mut salt = [0u8; LENGTH_SALT];
+		let mut iv = [0u8; LENGTH_IV];
+		crate::cipher::random_bytes(&mut salt).context("Could not generate salt")?;
+		crate::cipher::random_bytes(&mut iv).context("Could not generate iv")?;
+
+		Self::with_salt_iv(writer, password, &salt, &iv, version)
+	}
+
+	/// Create a backup encrypted with `password`, using the given `salt` and `iv`
+	pub fn with_salt_iv(
+		mut writer: W,
+		password: &[u8],
+		salt: &[u8],
+		iv: &[u8],
+		version: u32,
+	) -> Result<Self, crate::error::Error> {
+		if version > MAX_VERSION {
+			return Err(crate::error::Error::Output(format!(
+				"Backup version {} cannot be written",
+				version
+			)));
+		}
+		if iv.len() != LENGTH_IV {
+			return Err(crate::error::Error::Output(format!(
+				"iv has to be {} bytes long",
+				LENGTH_IV
+			)));
+		}
+
+		// the header is the only frame which is not encrypted
+		let header = crate::frame::Frame::Header {
+			salt: salt.to_vec(),
+			iv: iv.to_vec(),
+			version,
+			unknown_fields: Default::default(),
+		};
+		let header = protobuf::Message::write_to_bytes(&header.to_backup_frame()?)
+			.context("Could not serialize header")?;
+		writer
+			.write_all(&(header.len() as u32).to_be_bytes())
+			.and_then(|_| writer.write_all(&header))
+			.context("Could not write header")?;
+
+		Ok(Self {
+			writer,
+			encrypter: crate::encrypter::Encrypter::new(password, salt, iv),
+			version,
+			finished: false,
+			broken: false,
+		})
+	}
+
+	/// Write `frame` and its attached data
+	///
+	/// Attachments, avatars and stickers must contain exactly as much data as given by their
+	/// length. A frame which could not be written completely cannot be undone, so no further
+	/// frames are accepted afterwards.
+	pub fn write_frame(
+		&mut self,
+		mut frame: crate::frame::Frame,
+	) -> Result<(), crate::error::Error> {
+		if self.finished {
+			return Err(crate::error::Error::Output(
+				"Backup is already finished".to_string(),
+			));
+		}
+		if self.broken {
+			return Err(crate::error::Error::Output(
+				"Backup is incomplete after a failed frame".to_string(),
+			));
+		}
+
+		match frame {
+			crate::frame::Frame::Header { .. } => {
+				return Err(crate::error::Error::Output(
+					"Header is written on creation of the backup".to_string(),
+				))
+			}
+			crate::frame::Frame::End { .. } => self.finished = true,
+			_ => (),
+		}
+
+		let mut message = frame
+			.to_backup_frame()
+			.and_then(|x| protobuf::Message::write_to_bytes(&x).map_err(crate::error::Error::from))
+			.with_context(|| format!("Could not serialize frame: {}", frame))?;
+		self.broken = true;
+		self.write_message(&mut message)
+			.with_context(|| format!("Could not write frame: {}", frame))?;
+
+		if let Some(length) = frame.get_data_length() {
+			let mut data = frame
+				.take_data()
+				.ok_or_else(|| crate::error::Error::MissingData(frame.to_string()))?;
+			self.write_data(&mut data, length)
+				.with_context(|| format!("Could not write data of frame: {}", frame))?;
+		}
+
+		self.broken = false;
+		Ok(())
+	}
+
+	/// Write end frame and return the underlying writer
+	pub fn finish(mut self) -> Result<W, crate::error::Error> {
+		if !self.finished {
+			self.write_frame(crate::frame::Frame::End {
+				unknown_fields: Default::default(),
+			})?;
+		}
+
+		self.writer.flush().context("Could not flush backup")?;
+		Ok(self.writer)
+	}
+
+	/// Encrypt `message` in place and write it as frame
+	fn write_message(&mut self, message: &mut [u8]) -> Result<(), crate::error::Error> {
+		let mut len = ((message.len() + crate::decrypter::LENGTH_HMAC) as u32).to_be_bytes();
+
+		// Since backup version 1 the frame length is encrypted and part of the frame's MAC.
+		if self.version >= 1 {
+			self.encrypter.encrypt(&mut len);
+		}
+		self.encrypter.encrypt(message);
+		let hmac = self.encrypter.finalize_mac();
+		self.encrypter.increase_iv();
+
+		self.writer.write_all(&len)?;
+		self.writer.write_all(message)?;
+		self.writer.write_all(&hmac)?;
+		Ok(())
+	}
+
+	fn write_data(
+		&mut self,
+		data: &mut dyn Read,
+		length: usize,
+	) -> Result<(), crate::error::Error> {
+		// Attached data need an update of MAC with IV.
+		self.encrypter.mac_update_with_iv();
+
+		let mut chunk = vec![0u8; std::cmp::min(length, CHUNK_SIZE)];
+		let mut remaining = length;
+		while remaining > 0 {
+			let chunk = &mut chunk[..std::cmp::min(remaining, CHUNK_SIZE)];
+			data.read_exact(chunk).map_err(|e| match e.kind() {
+				std::io::ErrorKind::UnexpectedEof => {
+					crate::error::Error::Output(format!("data is shorter than {} bytes", length))
+				}
+				_ => crate::error::Error::from(e).context("Could not read data"),
+			})?;
+			self.encrypter.encrypt(chunk);
+			self.writer.write_all(chunk)?;
+			remaining -= chunk.len();
+		}
+
+		if data.read(&mut [0u8; 1])? > 0 {
+			return Err(crate::error::Error::Output(format!(
+				"data is longer than {} bytes",
+				length
+			)));
+		}
+
+		let hmac = self.encrypter.finalize_mac();
+		self.encrypter.increase_iv();
+		self.writer.write_all(&hmac)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PASSWORD: &[u8] = b"123451234512345123451234512345";
+
+	fn encode(version: u32) -> Vec<u8> {
+		let mut encoder = Encoder::new(Vec::new(), PASSWORD, version).unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Version {
+				version: 57,
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Statement {
+				statement: String::from("INSERT INTO sms VALUES (?, ?, ?, ?, ?)"),
+				parameter: vec![
+					rusqlite::types::Value::Integer(-1),
+					rusqlite::types::Value::Real(0.5),
+					rusqlite::types::Value::Text(String::from("hello")),
+					rusqlite::types::Value::Blob(vec![0, 1, 2]),
+					rusqlite::types::Value::Null,
+				],
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Attachment {
+				data_length: 100_000,
+				id: 1,
+				row: 2,
+				data: Some(Box::new(std::io::Cursor::new(vec![7u8; 100_000]))),
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Avatar {
+				data_length: 3,
+				name: String::from("avatar"),
+				recipient_id: String::from("5"),
+				data: Some(Box::new(&b"abc"[..])),
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		encoder.finish().unwrap()
+	}
+
+	#[test]
+	fn round_trip() {
+		for version in 0..=MAX_VERSION {
+			let backup = encode(version);
+			let mut input = crate::input::InputFile::new(
+				std::io::Cursor::new(backup),
+				None,
+				&crate::key::Secret::Password(zeroize::Zeroizing::new(PASSWORD.to_vec())),
+				Default::default(),
+			)
+			.unwrap();
+
+			match input.next().unwrap().unwrap() {
+				crate::frame::Frame::Version { version, .. } => assert_eq!(version, 57),
+				x => panic!("unexpected frame: {}", x),
+			}
+
+			match input.next().unwrap().unwrap() {
+				crate::frame::Frame::Statement { parameter, .. } => assert_eq!(
+					parameter,
+					vec![
+						rusqlite::types::Value::Integer(-1),
+						rusqlite::types::Value::Real(0.5),
+						rusqlite::types::Value::Text(String::from("hello")),
+						rusqlite::types::Value::Blob(vec![0, 1, 2]),
+						rusqlite::types::Value::Null,
+					]
+				),
+				x => panic!("unexpected frame: {}", x),
+			}
+
+			assert!(matches!(
+				input.next().unwrap().unwrap(),
+				crate::frame::Frame::Attachment { id: 1, row: 2, .. }
+			));
+			let mut data = Vec::new();
+			input.read_attachment(&mut data).unwrap();
+			assert_eq!(data, vec![7u8; 100_000]);
+
+			match input.next().unwrap().unwrap() {
+				crate::frame::Frame::Avatar {
+					name, recipient_id, ..
+				} => {
+					assert_eq!(name, "avatar");
+					assert_eq!(recipient_id, "5");
+				}
+				x => panic!("unexpected frame: {}", x),
+			}
+			let mut data = Vec::new();
+			input.read_attachment(&mut data).unwrap();
+			assert_eq!(data, b"abc");
+
+			// end frame
+			assert!(input.next().is_none());
+		}
+	}
+
+	#[test]
+	fn data_length_mismatch() {
+		let mut encoder =
+			Encoder::with_salt_iv(Vec::new(), PASSWORD, &[0; 32], &[0; 16], 1).unwrap();
+		assert!(encoder
+			.write_frame(crate::frame::Frame::Sticker {
+				data_length: 4,
+				row: 1,
+				data: Some(Box::new(&b"abc"[..])),
+				unknown_fields: Default::default(),
+			})
+			.is_err());
+	}
+}
@@ -0,0 +1,47 @@
+use crate::cipher::StreamCipher;
+use hmac::crypto_mac::Mac;
+use hmac::crypto_mac::NewMac;
+
+/// Encrypt bytes
+///
+/// Counterpart of `crate::decrypter::Decrypter`. All data encrypted between two calls of
+/// `increase_iv` is part of the same AES-CTR stream. The cipher context is created once and only
+/// restarted with the new iv for every frame.
+pub struct Encrypter {
+	mac: hmac::Hmac<sha2::Sha256>,
+	iv: Vec<u8>,
+	cipher: crate::cipher::Aes256Ctr,
+}
+
+impl Encrypter {
+	pub fn new(password: &[u8], salt: &[u8], iv: &[u8]) -> Self {
+		let (cipher_key, mac_key) = crate::decrypter::derive_keys(password, salt);
+
+		Self {
+			mac: hmac::Hmac::<sha2::Sha256>::new_varkey(&mac_key).unwrap(),
+			cipher: crate::cipher::Aes256Ctr::new(&cipher_key, iv),
+			iv: iv.to_vec(),
+		}
+	}
+
+	/// Encrypt `data` in place and add the encrypted data to the MAC
+	pub fn encrypt(&mut self, data: &mut [u8]) {
+		self.cipher.apply_keystream(data);
+		self.mac.update(data);
+	}
+
+	pub fn mac_update_with_iv(&mut self) {
+		self.mac.update(&self.iv);
+	}
+
+	/// Return the truncated MAC of all data encrypted since the last call and reset it
+	pub fn finalize_mac(&mut self) -> Vec<u8> {
+		self.mac.finalize_reset().into_bytes()[..crate::decrypter::LENGTH_HMAC].to_vec()
+	}
+
+	/// Start a new stream with the next counter, used for every frame and attachment
+	pub fn increase_iv(&mut self) {
+		crate::decrypter::increase_counter(&mut self.iv);
+		self.cipher.set_iv(&self.iv);
+	}
+}
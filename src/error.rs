@@ -0,0 +1,300 @@
+/// Errors returned while reading, converting and writing backups
+///
+/// Wrapped errors of other crates carry a context describing what failed, it can be set with
+/// `Context`.
+#[derive(Debug)]
+pub enum Error {
+	/// Password or key does not decrypt the backup
+	WrongPassword,
+	/// Key was derived for a backup with another salt
+	WrongBackup {
+		/// Fingerprint of the salt the key was derived for
+		fingerprint: Vec<u8>,
+		/// Fingerprint of the salt of the backup
+		expected: Vec<u8>,
+	},
+	/// No password matches the given password pattern
+	PasswordNotFound,
+	/// MAC of a frame or its attached data does not match
+	Mac {
+		/// Number of the frame
+		frame: usize,
+		/// Offset of the frame in the backup in bytes
+		offset: u64,
+	},
+	/// Backup ends after a complete frame but without an end frame
+	MissingEnd {
+		/// Number of the frame
+		frame: usize,
+		/// Offset of the frame in the backup in bytes
+		offset: u64,
+	},
+	/// Backup ends in the middle of a frame
+	Truncated {
+		/// Number of the frame
+		frame: usize,
+		/// Offset of the frame in the backup in bytes
+		offset: u64,
+	},
+	/// Frame length exceeds `Limits::frame_length`
+	FrameTooLarge {
+		/// Number of the frame
+		frame: usize,
+		/// Offset of the frame in the backup in bytes
+		offset: u64,
+		/// Length of the frame in bytes
+		length: usize,
+		/// Maximum length in bytes
+		limit: usize,
+	},
+	/// Length of attached data exceeds `Limits::attachment_length`
+	AttachmentTooLarge {
+		/// Number of the frame
+		frame: usize,
+		/// Offset of the frame in the backup in bytes
+		offset: u64,
+		/// Length of the attached data in bytes
+		length: u64,
+		/// Maximum length in bytes
+		limit: u64,
+	},
+	/// Frame is authentic, but its content is implausible
+	Corrupted {
+		/// Number of the frame
+		frame: usize,
+		/// Offset of the frame in the backup in bytes
+		offset: u64,
+		/// What is implausible about the frame
+		reason: String,
+	},
+	/// Frame could not be parsed or serialized
+	Protobuf {
+		/// What failed
+		context: String,
+		/// Wrapped error
+		source: protobuf::ProtobufError,
+	},
+	/// Frame type is not supported at this place
+	UnsupportedFrame(String),
+	/// Frame is expected to have attached data, but has none
+	MissingData(String),
+	/// Database statement failed
+	Sql {
+		/// What failed
+		context: String,
+		/// Wrapped error
+		source: rusqlite::Error,
+	},
+	/// Csv output failed
+	Csv {
+		/// What failed
+		context: String,
+		/// Wrapped error
+		source: csv::Error,
+	},
+	/// Output file already exists and may not be overwritten
+	OutputConflict(std::path::PathBuf),
+	/// Output cannot be written for other reasons
+	Output(String),
+	/// Invalid arguments, key file or password pattern
+	Config(String),
+	/// Reading or writing a file failed
+	Io {
+		/// What failed
+		context: String,
+		/// Wrapped error
+		source: std::io::Error,
+	},
+}
+
+impl Error {
+	/// Is the backup cut off before its end?
+	pub fn is_truncated(&self) -> bool {
+		matches!(self, Self::MissingEnd { .. } | Self::Truncated { .. })
+	}
+
+	/// Add `context` to the error
+	///
+	/// Errors with a precise location in the backup are returned unchanged.
+	pub fn context<C: std::fmt::Display>(mut self, context: C) -> Self {
+		match self {
+			Self::Protobuf {
+				context: ref mut x, ..
+			}
+			| Self::Sql {
+				context: ref mut x, ..
+			}
+			| Self::Csv {
+				context: ref mut x, ..
+			}
+			| Self::Io {
+				context: ref mut x, ..
+			}
+			| Self::Output(ref mut x)
+			| Self::Config(ref mut x) => {
+				*x = if x.is_empty() {
+					context.to_string()
+				} else {
+					format!("{}: {}", context, x)
+				}
+			}
+			_ => (),
+		}
+		self
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Protobuf { source, .. } => Some(source),
+			Self::Sql { source, .. } => Some(source),
+			Self::Csv { source, .. } => Some(source),
+			Self::Io { source, .. } => Some(source),
+			_ => None,
+		}
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::WrongPassword => write!(f, "Password is wrong"),
+			Self::WrongBackup {
+				fingerprint,
+				expected,
+			} => write!(
+				f,
+				"Key does not belong to this backup (salt fingerprint {}, expected {})",
+				crate::key::to_hex(fingerprint).as_str(),
+				crate::key::to_hex(expected).as_str()
+			),
+			Self::PasswordNotFound => write!(f, "No password matching the pattern found"),
+			Self::Mac { frame, offset } => write!(
+				f,
+				"MAC verification of frame number {} at offset {} failed",
+				frame, offset
+			),
+			Self::MissingEnd { frame, offset } => write!(
+				f,
+				"Backup ends before frame number {} at offset {} without an end frame",
+				frame, offset
+			),
+			Self::Truncated { frame, offset } => write!(
+				f,
+				"Backup ends within frame number {} starting at offset {}",
+				frame, offset
+			),
+			Self::FrameTooLarge {
+				frame,
+				offset,
+				length,
+				limit,
+			} => write!(
+				f,
+				"Frame number {} at offset {} has a length of {} bytes, exceeding the limit of {} bytes",
+				frame, offset, length, limit
+			),
+			Self::AttachmentTooLarge {
+				frame,
+				offset,
+				length,
+				limit,
+			} => write!(
+				f,
+				"Frame number {} at offset {} has attached data of {} bytes, exceeding the limit of {} bytes",
+				frame, offset, length, limit
+			),
+			Self::Corrupted {
+				frame,
+				offset,
+				reason,
+			} => write!(
+				f,
+				"Frame number {} at offset {} is corrupted: {}",
+				frame, offset, reason
+			),
+			Self::Protobuf { context, source } => with_context(f, context, source),
+			Self::UnsupportedFrame(x) => write!(f, "Unsupported frame found: {}", x),
+			Self::MissingData(x) => write!(f, "{} has no attached data", x),
+			Self::Sql { context, source } => with_context(f, context, source),
+			Self::Csv { context, source } => with_context(f, context, source),
+			Self::OutputConflict(x) => {
+				write!(
+					f,
+					"File does already exist: {}. Try -f",
+					x.to_string_lossy()
+				)
+			}
+			Self::Output(x) | Self::Config(x) => write!(f, "{}", x),
+			Self::Io { context, source } => with_context(f, context, source),
+		}
+	}
+}
+
+fn with_context(
+	f: &mut std::fmt::Formatter<'_>,
+	context: &str,
+	source: &dyn std::fmt::Display,
+) -> std::fmt::Result {
+	if context.is_empty() {
+		write!(f, "{}", source)
+	} else {
+		write!(f, "{}: {}", context, source)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(source: std::io::Error) -> Self {
+		Self::Io {
+			context: String::new(),
+			source,
+		}
+	}
+}
+
+impl From<protobuf::ProtobufError> for Error {
+	fn from(source: protobuf::ProtobufError) -> Self {
+		Self::Protobuf {
+			context: String::new(),
+			source,
+		}
+	}
+}
+
+impl From<rusqlite::Error> for Error {
+	fn from(source: rusqlite::Error) -> Self {
+		Self::Sql {
+			context: String::new(),
+			source,
+		}
+	}
+}
+
+impl From<csv::Error> for Error {
+	fn from(source: csv::Error) -> Self {
+		Self::Csv {
+			context: String::new(),
+			source,
+		}
+	}
+}
+
+/// Add context to errors of results
+pub trait Context<T> {
+	/// Describe what failed with `context`
+	fn context<C: std::fmt::Display>(self, context: C) -> Result<T, Error>;
+
+	/// Describe what failed with the context returned by `f`, it is only called on errors
+	fn with_context<C: std::fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+	fn context<C: std::fmt::Display>(self, context: C) -> Result<T, Error> {
+		self.map_err(|e| e.into().context(context))
+	}
+
+	fn with_context<C: std::fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T, Error> {
+		self.map_err(|e| e.into().context(f()))
+	}
+}
@@ -1,41 +1,94 @@
-use anyhow::Context;
+use crate::error::Context;
+use serde::ser::SerializeMap;
 use std::convert::TryInto;
 
+pub use crate::Backups::{KeyValue, SharedPreference};
+
 /// Frame
+///
+/// Attachments, avatars and stickers are followed by attached data of `data_length` bytes in the
+/// backup, `data` is only set once it has been read.
 pub enum Frame {
+	/// Unencrypted first frame with the parameters to derive the keys
 	Header {
+		/// Salt of the key derivation
 		salt: Vec<u8>,
+		/// Initialization vector of the encryption
 		iv: Vec<u8>,
+		/// Version of the backup format
+		version: u32,
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
 	},
+	/// SQL statement restoring the database
 	Statement {
+		/// Statement, parameters are bound to `?`
 		statement: String,
+		/// Parameters of the statement
 		parameter: Vec<rusqlite::types::Value>,
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
 	},
+	/// Entry of the Android shared preferences
 	Preference {
+		/// Protobuf message of the preference
 		preference: crate::Backups::SharedPreference,
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
 	},
+	/// File attached to a message
 	Attachment {
+		/// Length of the attached data in bytes
 		data_length: usize,
+		/// Unique id of the attachment
 		id: u64,
+		/// Row of the attachment in the database
 		row: u64,
-		data: Option<Vec<u8>>,
+		/// Attached data
+		data: Option<Box<dyn std::io::Read + Send>>,
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
 	},
+	/// Version of the database
 	Version {
+		/// Database version
 		version: u32,
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
 	},
-	End,
+	/// Last frame of the backup
+	End {
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
+	},
+	/// Avatar image of a contact or group
 	Avatar {
+		/// Length of the attached data in bytes
 		data_length: usize,
+		/// Name of the avatar file, in older backups
 		name: String,
-		data: Option<Vec<u8>>,
+		/// Recipient the avatar belongs to, in newer backups
+		recipient_id: String,
+		/// Attached data
+		data: Option<Box<dyn std::io::Read + Send>>,
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
 	},
+	/// Image of an installed sticker
 	Sticker {
+		/// Length of the attached data in bytes
 		data_length: usize,
+		/// Row of the sticker in the database
 		row: u64,
-		data: Option<Vec<u8>>,
+		/// Attached data
+		data: Option<Box<dyn std::io::Read + Send>>,
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
 	},
+	/// Entry of the key value store
 	KeyValue {
-		key_value: crate::Backups::KeyValue
+		/// Protobuf message of the entry
+		key_value: crate::Backups::KeyValue,
 		    // optional string key          = 1;
     // optional bytes  blobValue    = 2;
     // optional bool   booleanValue = 3;
@@ -43,11 +96,52 @@ pub enum Frame {
     // optional int32  integerValue = 5;
     // optional int64  longValue    = 6;
     // optional string stringValue  = 7;
+		/// Fields of the frame unknown to this version
+		unknown_fields: UnknownFields,
+	},
+	/// Frame without any field known to this version, e.g. added by newer Signal versions
+	Unknown {
+		/// Serialized frame as read from the backup
+		data: Vec<u8>,
+		/// Numbers of the fields set in the frame
+		fields: Vec<u32>,
+	},
+}
+
+/// Protobuf fields of a frame unknown to this version, e.g. added by newer Signal versions
+///
+/// They are written back unchanged when the frame is converted to its protobuf message again.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnknownFields {
+	/// Unknown fields of the frame itself
+	frame: protobuf::UnknownFields,
+	/// Unknown fields of the message in the frame, e.g. the statement
+	///
+	/// Preferences and key value entries keep them in their protobuf message.
+	message: protobuf::UnknownFields,
+}
+
+impl UnknownFields {
+	/// Take unknown fields of `frame` and of its message with unknown fields `message`
+	fn take(
+		frame: &mut crate::Backups::BackupFrame,
+		message: &mut protobuf::UnknownFields,
+	) -> Self {
+		Self {
+			frame: std::mem::take(protobuf::Message::mut_unknown_fields(frame)),
+			message: std::mem::take(message),
+		}
 	}
 }
 
 impl Frame {
-	pub fn new(frame: &mut crate::Backups::BackupFrame) -> Self {
+	/// Convert protobuf message `frame` to a frame
+	///
+	/// `data` is the serialized `frame`, it is kept for frames without known fields.
+	pub(crate) fn new(
+		frame: &mut crate::Backups::BackupFrame,
+		data: &[u8],
+	) -> Result<Self, crate::error::Error> {
 		let mut fields_count = 0;
 		let mut ret: Option<Self> = None;
 
@@ -57,6 +151,11 @@ impl Frame {
 			ret = Some(Self::Header {
 				salt: header.take_salt(),
 				iv: header.take_iv(),
+				version: header.get_version(),
+				unknown_fields: UnknownFields::take(
+					frame,
+					protobuf::Message::mut_unknown_fields(&mut header),
+				),
 			});
 		};
 
@@ -65,25 +164,15 @@ impl Frame {
 			let mut statement = frame.take_statement();
 			ret = Some(Self::Statement {
 				statement: statement.take_statement(),
-				parameter: {
-					let mut params: Vec<rusqlite::types::Value> = Vec::new();
-					for param in statement.take_parameters().iter_mut() {
-						if param.has_stringParamter() {
-							params.push(param.take_stringParamter().into());
-						} else if param.has_integerParameter() {
-							params.push((param.get_integerParameter() as i64).into());
-						} else if param.has_doubleParameter() {
-							params.push(param.get_doubleParameter().into());
-						} else if param.has_blobParameter() {
-							params.push(param.take_blobParameter().into());
-						} else if param.has_nullparameter() {
-							params.push(rusqlite::types::Null.into());
-						} else {
-							panic!("Parameter type not known {:?}", param);
-						}
-					}
-					params
-				},
+				parameter: statement
+					.take_parameters()
+					.into_iter()
+					.map(parameter_to_value)
+					.collect::<Result<_, _>>()?,
+				unknown_fields: UnknownFields::take(
+					frame,
+					protobuf::Message::mut_unknown_fields(&mut statement),
+				),
 			});
 		};
 
@@ -91,30 +180,42 @@ impl Frame {
 			fields_count += 1;
 			ret = Some(Self::Preference {
 				preference: frame.take_preference(),
+				unknown_fields: UnknownFields::take(frame, &mut Default::default()),
 			});
 		};
 
 		if frame.has_attachment() {
 			fields_count += 1;
-			let attachment = frame.take_attachment();
+			let mut attachment = frame.take_attachment();
 			ret = Some(Self::Attachment {
 				data_length: attachment.get_length().try_into().unwrap(),
 				id: attachment.get_attachmentId(),
 				row: attachment.get_rowId(),
 				data: None,
+				unknown_fields: UnknownFields::take(
+					frame,
+					protobuf::Message::mut_unknown_fields(&mut attachment),
+				),
 			});
 		};
 
 		if frame.has_version() {
 			fields_count += 1;
+			let mut version = frame.take_version();
 			ret = Some(Self::Version {
-				version: frame.get_version().get_version(),
+				version: version.get_version(),
+				unknown_fields: UnknownFields::take(
+					frame,
+					protobuf::Message::mut_unknown_fields(&mut version),
+				),
 			});
 		};
 
 		if frame.has_end() {
 			fields_count += 1;
-			ret = Some(Self::End);
+			ret = Some(Self::End {
+				unknown_fields: UnknownFields::take(frame, &mut Default::default()),
+			});
 		};
 
 		if frame.has_avatar() {
@@ -123,17 +224,26 @@ impl Frame {
 			ret = Some(Self::Avatar {
 				data_length: avatar.get_length().try_into().unwrap(),
 				name: avatar.take_name(),
+				recipient_id: avatar.take_recipientId(),
 				data: None,
+				unknown_fields: UnknownFields::take(
+					frame,
+					protobuf::Message::mut_unknown_fields(&mut avatar),
+				),
 			});
 		};
 
 		if frame.has_sticker() {
 			fields_count += 1;
-			let sticker = frame.take_sticker();
+			let mut sticker = frame.take_sticker();
 			ret = Some(Self::Sticker {
 				data_length: sticker.get_length().try_into().unwrap(),
 				row: sticker.get_rowId(),
 				data: None,
+				unknown_fields: UnknownFields::take(
+					frame,
+					protobuf::Message::mut_unknown_fields(&mut sticker),
+				),
 			});
 		};
 
@@ -141,21 +251,272 @@ impl Frame {
 			fields_count += 1;
 			let key_value = frame.take_keyValue();
 			ret = Some(Self::KeyValue {
-				key_value
+				key_value,
+				unknown_fields: UnknownFields::take(frame, &mut Default::default()),
 			});
 		};
 
-		if fields_count != 1 {
-			panic!(
-				"Frame with an unsupported number of fields found, please report to author: {:?}",
-				frame
-			);
+		match (fields_count, ret) {
+			(1, Some(x)) => Ok(x),
+			(0, _) => Ok(Self::Unknown {
+				data: data.to_vec(),
+				fields: protobuf::Message::get_unknown_fields(frame)
+					.iter()
+					.map(|(number, _)| number)
+					.collect(),
+			}),
+			_ => Err(crate::error::Error::UnsupportedFrame(format!(
+				"Frame with {} fields: {:?}",
+				fields_count, frame
+			))),
+		}
+	}
+
+	/// Length of attached data of attachments, avatars and stickers
+	pub fn get_data_length(&self) -> Option<usize> {
+		match self {
+			Frame::Attachment { data_length, .. } => Some(*data_length),
+			Frame::Avatar { data_length, .. } => Some(*data_length),
+			Frame::Sticker { data_length, .. } => Some(*data_length),
+			_ => None,
+		}
+	}
+
+	/// Convert frame back to its protobuf message
+	///
+	/// Attached data is not part of the message. Its length has to fit into 32 bits.
+	pub(crate) fn to_backup_frame(&self) -> Result<crate::Backups::BackupFrame, crate::error::Error> {
+		let mut frame = crate::Backups::BackupFrame::new();
+
+		let unknown_fields = match self {
+			Self::Header {
+				salt,
+				iv,
+				version,
+				unknown_fields,
+			} => {
+				let mut header = crate::Backups::Header::new();
+				header.set_salt(salt.clone());
+				header.set_iv(iv.clone());
+				// version 0 backups do not contain a version field
+				if *version > 0 {
+					header.set_version(*version);
+				}
+				*protobuf::Message::mut_unknown_fields(&mut header) =
+					unknown_fields.message.clone();
+				frame.set_header(header);
+				unknown_fields
+			}
+			Self::Statement {
+				statement,
+				parameter,
+				unknown_fields,
+			} => {
+				let mut sql = crate::Backups::SqlStatement::new();
+				sql.set_statement(statement.clone());
+				for value in parameter {
+					sql.mut_parameters().push(value_to_parameter(value));
+				}
+				*protobuf::Message::mut_unknown_fields(&mut sql) = unknown_fields.message.clone();
+				frame.set_statement(sql);
+				unknown_fields
+			}
+			Self::Preference {
+				preference,
+				unknown_fields,
+			} => {
+				frame.set_preference(preference.clone());
+				unknown_fields
+			}
+			Self::Attachment {
+				data_length,
+				id,
+				row,
+				unknown_fields,
+				..
+			} => {
+				let mut attachment = crate::Backups::Attachment::new();
+				attachment.set_rowId(*row);
+				attachment.set_attachmentId(*id);
+				attachment.set_length(length_to_u32(*data_length)?);
+				*protobuf::Message::mut_unknown_fields(&mut attachment) =
+					unknown_fields.message.clone();
+				frame.set_attachment(attachment);
+				unknown_fields
+			}
+			Self::Version {
+				version,
+				unknown_fields,
+			} => {
+				let mut database_version = crate::Backups::DatabaseVersion::new();
+				database_version.set_version(*version);
+				*protobuf::Message::mut_unknown_fields(&mut database_version) =
+					unknown_fields.message.clone();
+				frame.set_version(database_version);
+				unknown_fields
+			}
+			Self::End { unknown_fields } => {
+				frame.set_end(true);
+				unknown_fields
+			}
+			Self::Avatar {
+				data_length,
+				name,
+				recipient_id,
+				unknown_fields,
+				..
+			} => {
+				let mut avatar = crate::Backups::Avatar::new();
+				avatar.set_name(name.clone());
+				if !recipient_id.is_empty() {
+					avatar.set_recipientId(recipient_id.clone());
+				}
+				avatar.set_length(length_to_u32(*data_length)?);
+				*protobuf::Message::mut_unknown_fields(&mut avatar) =
+					unknown_fields.message.clone();
+				frame.set_avatar(avatar);
+				unknown_fields
+			}
+			Self::Sticker {
+				data_length,
+				row,
+				unknown_fields,
+				..
+			} => {
+				let mut sticker = crate::Backups::Sticker::new();
+				sticker.set_rowId(*row);
+				sticker.set_length(length_to_u32(*data_length)?);
+				*protobuf::Message::mut_unknown_fields(&mut sticker) =
+					unknown_fields.message.clone();
+				frame.set_sticker(sticker);
+				unknown_fields
+			}
+			Self::KeyValue {
+				key_value,
+				unknown_fields,
+			} => {
+				frame.set_keyValue(key_value.clone());
+				unknown_fields
+			}
+			// the parser keeps unknown fields
+			Self::Unknown { data, .. } => {
+				protobuf::Message::merge_from_bytes(&mut frame, data)
+					.context("Could not parse unknown frame")?;
+				return Ok(frame);
+			}
 		};
+		*protobuf::Message::mut_unknown_fields(&mut frame) = unknown_fields.frame.clone();
+
+		Ok(frame)
+	}
 
-		ret.unwrap()
+	/// Copy frame without its attached data
+	pub fn copy_without_data(&self) -> Self {
+		match self {
+			Self::Header {
+				salt,
+				iv,
+				version,
+				unknown_fields,
+			} => Self::Header {
+				salt: salt.clone(),
+				iv: iv.clone(),
+				version: *version,
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::Statement {
+				statement,
+				parameter,
+				unknown_fields,
+			} => Self::Statement {
+				statement: statement.clone(),
+				parameter: parameter.clone(),
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::Preference {
+				preference,
+				unknown_fields,
+			} => Self::Preference {
+				preference: preference.clone(),
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::Attachment {
+				data_length,
+				id,
+				row,
+				unknown_fields,
+				..
+			} => Self::Attachment {
+				data_length: *data_length,
+				id: *id,
+				row: *row,
+				data: None,
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::Version {
+				version,
+				unknown_fields,
+			} => Self::Version {
+				version: *version,
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::End { unknown_fields } => Self::End {
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::Avatar {
+				data_length,
+				name,
+				recipient_id,
+				unknown_fields,
+				..
+			} => Self::Avatar {
+				data_length: *data_length,
+				name: name.clone(),
+				recipient_id: recipient_id.clone(),
+				data: None,
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::Sticker {
+				data_length,
+				row,
+				unknown_fields,
+				..
+			} => Self::Sticker {
+				data_length: *data_length,
+				row: *row,
+				data: None,
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::KeyValue {
+				key_value,
+				unknown_fields,
+			} => Self::KeyValue {
+				key_value: key_value.clone(),
+				unknown_fields: unknown_fields.clone(),
+			},
+			Self::Unknown { data, fields } => Self::Unknown {
+				data: data.clone(),
+				fields: fields.clone(),
+			},
+		}
 	}
 
-	pub fn set_data(&mut self, data_add: Vec<u8>) {
+	/// Take attached data of attachments, avatars and stickers
+	pub fn take_data(&mut self) -> Option<Box<dyn std::io::Read + Send>> {
+		match self {
+			Frame::Attachment { ref mut data, .. } => data.take(),
+			Frame::Avatar { ref mut data, .. } => data.take(),
+			Frame::Sticker { ref mut data, .. } => data.take(),
+			_ => None,
+		}
+	}
+
+	/// Set attached data of attachments, avatars and stickers
+	///
+	/// # Panics
+	///
+	/// Panics for frames without attached data.
+	pub fn set_data(&mut self, data_add: Box<dyn std::io::Read + Send>) {
 		match self {
 			Frame::Attachment { ref mut data, .. } => *data = Some(data_add),
 			Frame::Avatar { ref mut data, .. } => *data = Some(data_add),
@@ -168,32 +529,514 @@ impl Frame {
 impl std::fmt::Display for Frame {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
-			Self::Header { salt, iv } => write!(
+			Self::Header {
+				salt, iv, version, ..
+			} => write!(
 				f,
-				"Header Frame (salt: {:02X?} (length: {}), iv: {:02X?} (length: {}))",
+				"Header Frame (salt: {:02X?} (length: {}), iv: {:02X?} (length: {}), version: {})",
 				salt,
 				salt.len(),
 				iv,
-				iv.len()
+				iv.len(),
+				version
 			),
 			Self::Sticker { data_length, .. } => write!(f, "Sticker (size: {})", data_length),
 			Self::Attachment { data_length, .. } => write!(f, "Attachment (size: {})", data_length),
 			Self::Avatar { data_length, .. } => write!(f, "Avatar (size: {})", data_length),
 			Self::Preference { .. } => write!(f, "Preference"),
 			Self::Statement { .. } => write!(f, "Statement"),
-			Self::Version { version } => write!(f, "Version ({})", version),
-			Self::End => write!(f, "End"),
+			Self::Version { version, .. } => write!(f, "Version ({})", version),
+			Self::End { .. } => write!(f, "End"),
 			Self::KeyValue { .. } => write!(f, "KeyValue"),
+			Self::Unknown { data, fields } => {
+				write!(f, "Unknown (fields: {:?}, size: {})", fields, data.len())
+			}
+		}
+	}
+}
+
+/// Convert the length of attached data to the length field of a frame
+fn length_to_u32(length: usize) -> Result<u32, crate::error::Error> {
+	length.try_into().map_err(|_| {
+		crate::error::Error::Output(format!(
+			"attached data of {} bytes is too large for a backup",
+			length
+		))
+	})
+}
+
+/// Convert a statement parameter of a backup to a SQLite value
+///
+/// Exactly one field of `param` has to be set, otherwise an error is returned. SQLite integers
+/// are signed, but Signal stores them in the unsigned `integerParameter`: values above
+/// `i64::MAX` are negative integers in two's complement and converted back without loss. SQLite
+/// has no boolean type, booleans are integers 0 and 1 and stay integers. `nullparameter` has to
+/// be true.
+pub(crate) fn parameter_to_value(
+	mut param: crate::Backups::SqlStatement_SqlParameter,
+) -> Result<rusqlite::types::Value, crate::error::Error> {
+	let fields_count = [
+		param.has_stringParamter(),
+		param.has_integerParameter(),
+		param.has_doubleParameter(),
+		param.has_blobParameter(),
+		param.has_nullparameter(),
+	]
+	.iter()
+	.filter(|x| **x)
+	.count();
+	if fields_count != 1 || (param.has_nullparameter() && !param.get_nullparameter()) {
+		return Err(crate::error::Error::UnsupportedFrame(format!(
+			"Statement with invalid parameter: {:?}",
+			param
+		)));
+	}
+
+	Ok(if param.has_stringParamter() {
+		rusqlite::types::Value::Text(param.take_stringParamter())
+	} else if param.has_integerParameter() {
+		rusqlite::types::Value::Integer(param.get_integerParameter() as i64)
+	} else if param.has_doubleParameter() {
+		rusqlite::types::Value::Real(param.get_doubleParameter())
+	} else if param.has_blobParameter() {
+		rusqlite::types::Value::Blob(param.take_blobParameter())
+	} else {
+		rusqlite::types::Value::Null
+	})
+}
+
+/// Convert a SQLite value to a statement parameter, the inverse of `parameter_to_value`
+pub(crate) fn value_to_parameter(
+	value: &rusqlite::types::Value,
+) -> crate::Backups::SqlStatement_SqlParameter {
+	let mut param = crate::Backups::SqlStatement_SqlParameter::new();
+	match value {
+		rusqlite::types::Value::Null => param.set_nullparameter(true),
+		rusqlite::types::Value::Integer(x) => param.set_integerParameter(*x as u64),
+		rusqlite::types::Value::Real(x) => param.set_doubleParameter(*x),
+		rusqlite::types::Value::Text(x) => param.set_stringParamter(x.clone()),
+		rusqlite::types::Value::Blob(x) => param.set_blobParameter(x.clone()),
+	}
+	param
+}
+
+/// Serialize frame as map with its `type`
+///
+/// Binary values are encoded with base64, attached data is not serialized.
+impl serde::Serialize for Frame {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut map = serializer.serialize_map(None)?;
+		match self {
+			Self::Header {
+				salt, iv, version, ..
+			} => {
+				map.serialize_entry("type", "header")?;
+				map.serialize_entry("salt", &base64::encode(salt))?;
+				map.serialize_entry("iv", &base64::encode(iv))?;
+				map.serialize_entry("version", version)?;
+			}
+			Self::Statement {
+				statement,
+				parameter,
+				..
+			} => {
+				map.serialize_entry("type", "statement")?;
+				map.serialize_entry("statement", statement)?;
+				map.serialize_entry(
+					"parameter",
+					&parameter.iter().map(Parameter).collect::<Vec<_>>(),
+				)?;
+			}
+			Self::Preference { preference, .. } => {
+				map.serialize_entry("type", "preference")?;
+				map.serialize_entry("preference", preference)?;
+			}
+			Self::Attachment {
+				data_length,
+				id,
+				row,
+				..
+			} => {
+				map.serialize_entry("type", "attachment")?;
+				map.serialize_entry("id", id)?;
+				map.serialize_entry("row", row)?;
+				map.serialize_entry("data_length", data_length)?;
+			}
+			Self::Version { version, .. } => {
+				map.serialize_entry("type", "version")?;
+				map.serialize_entry("version", version)?;
+			}
+			Self::End { .. } => map.serialize_entry("type", "end")?,
+			Self::Avatar {
+				data_length,
+				name,
+				recipient_id,
+				..
+			} => {
+				map.serialize_entry("type", "avatar")?;
+				map.serialize_entry("name", name)?;
+				map.serialize_entry("recipient_id", recipient_id)?;
+				map.serialize_entry("data_length", data_length)?;
+			}
+			Self::Sticker {
+				data_length, row, ..
+			} => {
+				map.serialize_entry("type", "sticker")?;
+				map.serialize_entry("row", row)?;
+				map.serialize_entry("data_length", data_length)?;
+			}
+			Self::KeyValue { key_value, .. } => {
+				map.serialize_entry("type", "key_value")?;
+				map.serialize_entry("key_value", key_value)?;
+			}
+			Self::Unknown { data, fields } => {
+				map.serialize_entry("type", "unknown")?;
+				map.serialize_entry("fields", fields)?;
+				map.serialize_entry("data", &base64::encode(data))?;
+			}
+		}
+		map.end()
+	}
+}
+
+/// Parameter of a statement, blobs are serialized as map `{"blob": <base64>}`
+struct Parameter<'a>(&'a rusqlite::types::Value);
+
+impl serde::Serialize for Parameter<'_> {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		match self.0 {
+			rusqlite::types::Value::Null => serializer.serialize_unit(),
+			rusqlite::types::Value::Integer(x) => serializer.serialize_i64(*x),
+			rusqlite::types::Value::Real(x) => serializer.serialize_f64(*x),
+			rusqlite::types::Value::Text(x) => serializer.serialize_str(x),
+			rusqlite::types::Value::Blob(x) => {
+				let mut map = serializer.serialize_map(Some(1))?;
+				map.serialize_entry("blob", &base64::encode(x))?;
+				map.end()
+			}
+		}
+	}
+}
+
+/// Serialize the fields set in the preference, named like in the protobuf definition
+impl serde::Serialize for crate::Backups::SharedPreference {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut map = serializer.serialize_map(None)?;
+		if self.has_file() {
+			map.serialize_entry("file", self.get_file())?;
+		}
+		if self.has_key() {
+			map.serialize_entry("key", self.get_key())?;
+		}
+		if self.has_value() {
+			map.serialize_entry("value", self.get_value())?;
+		}
+		if self.has_booleanValue() {
+			map.serialize_entry("booleanValue", &self.get_booleanValue())?;
 		}
+		if self.get_isStringSetValue() {
+			map.serialize_entry("stringSetValue", self.get_stringSetValue())?;
+		}
+		map.end()
+	}
+}
+
+/// Serialize the fields set in the key value pair, named like in the protobuf definition
+impl serde::Serialize for crate::Backups::KeyValue {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut map = serializer.serialize_map(None)?;
+		if self.has_key() {
+			map.serialize_entry("key", self.get_key())?;
+		}
+		if self.has_blobValue() {
+			map.serialize_entry("blobValue", &base64::encode(self.get_blobValue()))?;
+		}
+		if self.has_booleanValue() {
+			map.serialize_entry("booleanValue", &self.get_booleanValue())?;
+		}
+		if self.has_floatValue() {
+			map.serialize_entry("floatValue", &self.get_floatValue())?;
+		}
+		if self.has_integerValue() {
+			map.serialize_entry("integerValue", &self.get_integerValue())?;
+		}
+		if self.has_longValue() {
+			map.serialize_entry("longValue", &self.get_longValue())?;
+		}
+		if self.has_stringValue() {
+			map.serialize_entry("stringValue", self.get_stringValue())?;
+		}
+		map.end()
 	}
 }
 
 impl std::convert::TryFrom<Vec<u8>> for Frame {
-	type Error = anyhow::Error;
+	type Error = crate::error::Error;
 
 	fn try_from(data: Vec<u8>) -> Result<Self, Self::Error> {
 		let mut frame = protobuf::Message::parse_from_bytes(&data)
 			.with_context(|| format!("Could not parse frame from {:02X?}", &data))?;
-		Ok(Self::new(&mut frame))
+		Self::new(&mut frame, &data)
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use proptest::prelude::*;
+	use std::convert::TryFrom;
+
+	#[test]
+	fn parameter() {
+		// integers above i64::MAX are negative
+		let mut param = crate::Backups::SqlStatement_SqlParameter::new();
+		param.set_integerParameter(u64::MAX);
+		assert_eq!(
+			parameter_to_value(param).unwrap(),
+			rusqlite::types::Value::Integer(-1)
+		);
+		let mut param = crate::Backups::SqlStatement_SqlParameter::new();
+		param.set_integerParameter(1 << 63);
+		assert_eq!(
+			parameter_to_value(param).unwrap(),
+			rusqlite::types::Value::Integer(i64::MIN)
+		);
+
+		let mut param = crate::Backups::SqlStatement_SqlParameter::new();
+		param.set_nullparameter(true);
+		assert_eq!(
+			parameter_to_value(param).unwrap(),
+			rusqlite::types::Value::Null
+		);
+
+		// malformed parameters
+		let mut param = crate::Backups::SqlStatement_SqlParameter::new();
+		param.set_nullparameter(false);
+		assert!(parameter_to_value(param).is_err());
+		let mut param = crate::Backups::SqlStatement_SqlParameter::new();
+		param.set_integerParameter(1);
+		param.set_stringParamter("1".to_string());
+		assert!(parameter_to_value(param).is_err());
+	}
+
+	proptest! {
+		#[test]
+		fn integer_round_trip(x: u64) {
+			let mut param = crate::Backups::SqlStatement_SqlParameter::new();
+			param.set_integerParameter(x);
+			let value = parameter_to_value(param).unwrap();
+			prop_assert_eq!(&value, &rusqlite::types::Value::Integer(x as i64));
+			prop_assert_eq!(value_to_parameter(&value).get_integerParameter(), x);
+		}
+
+		#[test]
+		fn value_round_trip(value in value()) {
+			let param = value_to_parameter(&value);
+			prop_assert_eq!(parameter_to_value(param).unwrap(), value);
+		}
+	}
+
+	/// Strategy for SQLite values, without NaN as SQLite stores it as NULL
+	pub fn value() -> impl Strategy<Value = rusqlite::types::Value> {
+		prop_oneof![
+			Just(rusqlite::types::Value::Null),
+			any::<i64>().prop_map(rusqlite::types::Value::Integer),
+			any::<f64>()
+				.prop_filter("NaN", |x| !x.is_nan())
+				.prop_map(rusqlite::types::Value::Real),
+			any::<String>().prop_map(rusqlite::types::Value::Text),
+			proptest::collection::vec(any::<u8>(), 0..100).prop_map(rusqlite::types::Value::Blob),
+		]
+	}
+
+	#[test]
+	fn unknown_fields() {
+		// field 42 with varint 1
+		let data = vec![0xd0, 0x02, 0x01];
+		match Frame::try_from(data.clone()).unwrap() {
+			Frame::Unknown { data: x, fields: y } => {
+				assert_eq!(x, data);
+				assert_eq!(y, vec![42]);
+			}
+			x => panic!("unexpected frame: {}", x),
+		}
+
+		// unknown fields are written back
+		let frame = Frame::Unknown {
+			data: data.clone(),
+			fields: vec![42],
+		};
+		assert_eq!(
+			protobuf::Message::write_to_bytes(&frame.to_backup_frame().unwrap()).unwrap(),
+			data
+		);
+
+		// malformed data is not written back
+		let frame = Frame::Unknown {
+			data: vec![0xd0],
+			fields: vec![42],
+		};
+		assert!(frame.to_backup_frame().is_err());
+
+		// unknown fields of known frames and of their messages are kept
+		for frame in with_unknown_fields() {
+			let data = protobuf::Message::write_to_bytes(&frame).unwrap();
+			let frame = Frame::try_from(data.clone()).unwrap();
+			assert!(!matches!(frame, Frame::Unknown { .. }));
+			assert_eq!(
+				protobuf::Message::write_to_bytes(&frame.to_backup_frame().unwrap()).unwrap(),
+				data
+			);
+			assert_eq!(
+				protobuf::Message::write_to_bytes(
+					&frame.copy_without_data().to_backup_frame().unwrap()
+				)
+				.unwrap(),
+				data
+			);
+		}
+	}
+
+	/// Protobuf messages of every kind of frame, with unknown fields in the frame and its message
+	pub fn with_unknown_fields() -> Vec<crate::Backups::BackupFrame> {
+		let mut frames = vec![crate::Backups::BackupFrame::new(); 9];
+
+		let mut header = crate::Backups::Header::new();
+		header.set_salt(vec![1; 32]);
+		header.set_iv(vec![2; 16]);
+		header.set_version(1);
+		protobuf::Message::mut_unknown_fields(&mut header).add_varint(42, 1);
+		frames[0].set_header(header);
+
+		let mut statement = crate::Backups::SqlStatement::new();
+		statement.set_statement("SELECT 1".to_string());
+		protobuf::Message::mut_unknown_fields(&mut statement).add_varint(42, 1);
+		frames[1].set_statement(statement);
+
+		let mut preference = crate::Backups::SharedPreference::new();
+		preference.set_file("file".to_string());
+		preference.set_key("key".to_string());
+		preference.set_value("value".to_string());
+		protobuf::Message::mut_unknown_fields(&mut preference).add_varint(42, 1);
+		frames[2].set_preference(preference);
+
+		let mut attachment = crate::Backups::Attachment::new();
+		attachment.set_rowId(1);
+		attachment.set_attachmentId(2);
+		attachment.set_length(3);
+		protobuf::Message::mut_unknown_fields(&mut attachment).add_varint(42, 1);
+		frames[3].set_attachment(attachment);
+
+		let mut version = crate::Backups::DatabaseVersion::new();
+		version.set_version(57);
+		protobuf::Message::mut_unknown_fields(&mut version).add_varint(42, 1);
+		frames[4].set_version(version);
+
+		frames[5].set_end(true);
+
+		let mut avatar = crate::Backups::Avatar::new();
+		avatar.set_name("avatar".to_string());
+		avatar.set_recipientId("5".to_string());
+		avatar.set_length(2);
+		protobuf::Message::mut_unknown_fields(&mut avatar).add_varint(42, 1);
+		frames[6].set_avatar(avatar);
+
+		let mut sticker = crate::Backups::Sticker::new();
+		sticker.set_rowId(4);
+		sticker.set_length(1);
+		protobuf::Message::mut_unknown_fields(&mut sticker).add_varint(42, 1);
+		frames[7].set_sticker(sticker);
+
+		let mut key_value = crate::Backups::KeyValue::new();
+		key_value.set_key("key".to_string());
+		key_value.set_stringValue("value".to_string());
+		protobuf::Message::mut_unknown_fields(&mut key_value).add_varint(42, 1);
+		frames[8].set_keyValue(key_value);
+
+		for frame in &mut frames {
+			protobuf::Message::mut_unknown_fields(frame).add_varint(43, 2);
+		}
+		frames
+	}
+
+	#[test]
+	#[cfg(target_pointer_width = "64")]
+	fn data_length_too_large() {
+		let frame = Frame::Sticker {
+			data_length: 1 << 32,
+			row: 1,
+			data: None,
+			unknown_fields: Default::default(),
+		};
+		assert!(frame.to_backup_frame().is_err());
+	}
+
+	#[test]
+	fn serialize() {
+		let frame = Frame::Statement {
+			statement: "INSERT INTO x VALUES (?, ?, ?, ?, ?)".to_string(),
+			parameter: vec![
+				rusqlite::types::Value::Null,
+				rusqlite::types::Value::Integer(-1),
+				rusqlite::types::Value::Real(0.5),
+				rusqlite::types::Value::Text("a".to_string()),
+				rusqlite::types::Value::Blob(vec![0, 1, 2]),
+			],
+			unknown_fields: Default::default(),
+		};
+		assert_eq!(
+			serde_json::to_string(&frame).unwrap(),
+			r#"{"type":"statement","statement":"INSERT INTO x VALUES (?, ?, ?, ?, ?)","parameter":[null,-1,0.5,"a",{"blob":"AAEC"}]}"#
+		);
+
+		let mut preference = crate::Backups::SharedPreference::new();
+		preference.set_file("file".to_string());
+		preference.set_key("key".to_string());
+		preference.set_booleanValue(true);
+		let frame = Frame::Preference {
+			preference,
+			unknown_fields: Default::default(),
+		};
+		assert_eq!(
+			serde_json::to_string(&frame).unwrap(),
+			r#"{"type":"preference","preference":{"file":"file","key":"key","booleanValue":true}}"#
+		);
+
+		let mut key_value = crate::Backups::KeyValue::new();
+		key_value.set_key("key".to_string());
+		key_value.set_blobValue(vec![255]);
+		let frame = Frame::KeyValue {
+			key_value,
+			unknown_fields: Default::default(),
+		};
+		assert_eq!(
+			serde_json::to_string(&frame).unwrap(),
+			r#"{"type":"key_value","key_value":{"key":"key","blobValue":"/w=="}}"#
+		);
+	}
+
+	#[test]
+	fn unsupported() {
+		// statement with a parameter without known fields
+		let mut frame = crate::Backups::BackupFrame::new();
+		let mut statement = crate::Backups::SqlStatement::new();
+		statement.set_statement("INSERT INTO x VALUES (?)".to_string());
+		statement
+			.mut_parameters()
+			.push(crate::Backups::SqlStatement_SqlParameter::new());
+		frame.set_statement(statement);
+		let data = protobuf::Message::write_to_bytes(&frame).unwrap();
+		assert!(matches!(
+			Frame::try_from(data),
+			Err(crate::error::Error::UnsupportedFrame(_))
+		));
+
+		// several fields
+		frame.set_end(true);
+		let data = protobuf::Message::write_to_bytes(&frame).unwrap();
+		assert!(matches!(
+			Frame::try_from(data),
+			Err(crate::error::Error::UnsupportedFrame(_))
+		));
 	}
 }
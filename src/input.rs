@@ -1,164 +1,986 @@
-use anyhow::anyhow;
-use anyhow::Context;
+use crate::error::Context;
+use byteorder::ByteOrder;
 use byteorder::ReadBytesExt;
-use log::{debug, info};
+use log::{debug, info, warn};
 use std::convert::TryInto;
 use std::io::Read;
+use std::io::Write;
+
+/// Size of chunks in which attached data is decrypted
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Maximum number of frames which may be lost in a corrupted region of the backup
+///
+/// When resynchronising in recovery mode, only iv counters in this range are tested.
+const RECOVER_MAX_LOST_FRAMES: u32 = 1024;
+
+/// Maximum frame length considered when resynchronising in recovery mode
+const RECOVER_MAX_FRAME_LENGTH: usize = 1024 * 1024;
+
+/// Upper bounds for lengths read from the backup
+///
+/// Lengths are read from the backup before they can be authenticated, so a corrupted or
+/// malicious backup could otherwise request arbitrarily large allocations.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+	/// Maximum length of a frame in bytes, including its MAC
+	pub frame_length: usize,
+	/// Maximum length of the data attached to attachments, avatars and stickers in bytes
+	///
+	/// Attached data is streamed, so this limit may exceed the address space.
+	pub attachment_length: u64,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			frame_length: 64 * 1024 * 1024,
+			attachment_length: 2 * 1024 * 1024 * 1024,
+		}
+	}
+}
+
+/// Options of reading a backup
+#[derive(Clone, Copy, Debug)]
+pub struct InputOptions {
+	/// Verify the MAC of frames and attached data
+	pub verify_mac: bool,
+	/// Skip corrupted frames instead of returning an error
+	pub recover: bool,
+	/// Upper bounds for lengths read from the backup
+	pub limits: Limits,
+}
+
+impl Default for InputOptions {
+	fn default() -> Self {
+		Self {
+			verify_mac: true,
+			recover: false,
+			limits: Limits::default(),
+		}
+	}
+}
+
+/// Reader which keeps track of its position and allows to push back read bytes
+///
+/// While `journal` is set, all read bytes are recorded, so they can be pushed back if a frame
+/// turns out to be corrupted.
+struct Reader<R: Read> {
+	inner: std::io::BufReader<R>,
+	pushback: std::collections::VecDeque<u8>,
+	journal: Option<Vec<u8>>,
+	position: u64,
+}
+
+impl<R: Read> Reader<R> {
+	fn new(inner: R) -> Self {
+		Self {
+			inner: std::io::BufReader::new(inner),
+			pushback: std::collections::VecDeque::new(),
+			journal: None,
+			position: 0,
+		}
+	}
+
+	/// Push back `data`, it is returned again by the next reads
+	fn unread(&mut self, data: &[u8]) {
+		for x in data.iter().rev() {
+			self.pushback.push_front(*x);
+		}
+		self.position -= data.len() as u64;
+	}
+
+	/// Start recording read bytes
+	fn start_journal(&mut self) {
+		self.journal = Some(Vec::new());
+	}
+
+	/// Stop recording read bytes and return them
+	fn take_journal(&mut self) -> Vec<u8> {
+		self.journal.take().unwrap_or_default()
+	}
+}
+
+impl<R: Read> Read for Reader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let count = if self.pushback.is_empty() {
+			self.inner.read(buf)?
+		} else {
+			let count = std::cmp::min(buf.len(), self.pushback.len());
+			for (x, y) in buf.iter_mut().zip(self.pushback.drain(..count)) {
+				*x = y;
+			}
+			count
+		};
+
+		if let Some(ref mut journal) = self.journal {
+			journal.extend_from_slice(&buf[..count]);
+		}
+		self.position += count as u64;
+
+		Ok(count)
+	}
+}
 
 /// Read input file
-pub struct InputFile {
-	reader: std::io::BufReader<std::fs::File>,
+///
+/// The backup can be read from any source implementing `std::io::Read`, e.g. a file, stdin or a
+/// pipe.
+pub struct InputFile<R: Read> {
+	reader: Reader<R>,
 	decrypter: crate::decrypter::Decrypter,
+	key: crate::key::BackupKey,
 	count_frame: usize,
-	count_byte: usize,
-	file_bytes: u64,
+	file_bytes: Option<u64>,
+	version: u32,
+	recover: bool,
+	limits: Limits,
+	count_lost_frame: usize,
+	count_lost_byte: usize,
+	offset_frame: u64,
+	number_frame: usize,
+	pending_data: Option<usize>,
+	finished: bool,
 }
 
-impl InputFile {
-	pub fn new(
+impl InputFile<Box<dyn Read + Send>> {
+	/// Open backup file at `path`
+	///
+	/// If `path` is `-`, the backup is read from stdin.
+	pub fn open(
 		path: &std::path::Path,
-		password: &[u8],
-		verify_mac: bool,
-	) -> Result<Self, anyhow::Error> {
+		secret: &crate::key::Secret,
+		options: InputOptions,
+	) -> Result<Self, crate::error::Error> {
+		if path == std::path::Path::new("-") {
+			info!("Input file: stdin");
+			return Self::new(Box::new(std::io::stdin()), None, secret, options);
+		}
+
 		// open file
 		info!("Input file: {}", &path.to_string_lossy());
 		let file = std::fs::File::open(path)
 			.with_context(|| format!("Could not open backup file: {}", path.to_string_lossy()))?;
-		let file_bytes = file.metadata().unwrap().len();
-		let mut reader = std::io::BufReader::new(file);
+		let file_bytes = file.metadata().ok().map(|x| x.len());
+
+		Self::new(Box::new(file), file_bytes, secret, options)
+	}
+}
+
+impl<R: Read> InputFile<R> {
+	/// Read backup from `reader`
+	///
+	/// `file_bytes` is the total size of the backup, if known. It is only used to display the
+	/// progress. The keys to decrypt the backup are derived from or checked against its salt
+	/// using `secret`. If `options.recover` is set, corrupted frames are skipped instead of
+	/// returning an error. Frames and attached data exceeding `options.limits` are rejected.
+	pub fn new(
+		reader: R,
+		file_bytes: Option<u64>,
+		secret: &crate::key::Secret,
+		options: InputOptions,
+	) -> Result<Self, crate::error::Error> {
+		let InputOptions {
+			verify_mac,
+			recover,
+			limits,
+		} = options;
+		let mut reader = Reader::new(reader);
 
 		// create decrypter
 		// - read first frame
+		let truncated = |e: std::io::Error| -> crate::error::Error {
+			if e.kind() == std::io::ErrorKind::UnexpectedEof {
+				crate::error::Error::Truncated {
+					frame: 0,
+					offset: 0,
+				}
+			} else {
+				e.into()
+			}
+		};
 		let len: usize = reader
 			.read_u32::<byteorder::BigEndian>()
-			.unwrap()
+			.map_err(truncated)?
 			.try_into()
 			.unwrap();
+		if len > limits.frame_length {
+			return Err(crate::error::Error::FrameTooLarge {
+				frame: 0,
+				offset: 0,
+				length: len,
+				limit: limits.frame_length,
+			});
+		}
 		let mut frame = vec![0u8; len];
-		reader.read_exact(&mut frame)?;
+		reader.read_exact(&mut frame).map_err(truncated)?;
 		let frame: crate::frame::Frame = frame.try_into()?;
 		debug!("Frame type: {}", &frame);
 
 		// check that frame is a header and return
 		match &frame {
-			crate::frame::Frame::Header { salt, iv } => Ok(Self {
-				reader,
-				decrypter: crate::decrypter::Decrypter::new(&password, &salt, &iv, verify_mac),
-				count_frame: 1,
-				// We already read `len` and 4 bytes with read_u32
-				// There are 16 bytes missing somewhere independent of the input
-				// file. However, I don't know why.
-				count_byte: len + std::mem::size_of::<u32>() + 16,
-				file_bytes,
+			crate::frame::Frame::Header {
+				salt, iv, version, ..
+			} => {
+				let key = secret.key(salt)?;
+
+				Ok(Self {
+					reader,
+					decrypter: crate::decrypter::Decrypter::new(&key, iv, verify_mac),
+					key,
+					count_frame: 1,
+					file_bytes,
+					version: *version,
+					recover,
+					limits,
+					count_lost_frame: 0,
+					count_lost_byte: 0,
+					offset_frame: 0,
+					number_frame: 0,
+					pending_data: None,
+					finished: false,
+				})
+			}
+			_ => Err(crate::error::Error::Corrupted {
+				frame: 0,
+				offset: 0,
+				reason: "first frame is not a header".to_string(),
 			}),
-			_ => Err(anyhow!("first frame is not a header")),
 		}
 	}
 
-	fn read_data(
-		&mut self,
-		length: usize,
-		read_attachment: bool,
-	) -> Result<Vec<u8>, anyhow::Error> {
+	fn read_data(&mut self, length: usize) -> Result<Vec<u8>, crate::error::Error> {
 		let mut hmac = [0u8; crate::decrypter::LENGTH_HMAC];
-		let mut data;
-
-		// Reading files (attachments) need an update of MAC with IV.
-		// And their given length corresponds to file length but frame length corresponds
-		// to data length + hmac data.
-		if read_attachment {
-			self.decrypter.mac_update_with_iv();
-			data = vec![0u8; length];
-		} else {
-			data = vec![0u8; length - crate::decrypter::LENGTH_HMAC];
-		}
+
+		// frame length corresponds to data length + hmac data
+		let mut data = vec![0u8; length - crate::decrypter::LENGTH_HMAC];
 
 		// read data and decrypt
-		self.reader.read_exact(&mut data)?;
-		let data = self.decrypter.decrypt(&mut data);
+		self.read_exact(&mut data)?;
+		self.decrypter.decrypt(&mut data);
 
 		// read hmac
-		self.reader.read_exact(&mut hmac)?;
+		self.read_exact(&mut hmac)?;
 
 		// verify mac
-		self.decrypter.verify_mac(&hmac)?;
+		self.decrypter
+			.verify_mac(&hmac)
+			.map_err(|_| crate::error::Error::Mac {
+				frame: self.number_frame,
+				offset: self.offset_frame,
+			})?;
 		self.decrypter.increase_iv();
 
-		if read_attachment {
-			// we got file length, so we have to add 10 bytes for hmac data
-			self.count_byte += length + crate::decrypter::LENGTH_HMAC;
-		} else {
-			// in the case of frames, we add 4 bytes we have read to determine frame length
-			// (hmac data is already in length included)
-			self.count_byte += length + std::mem::size_of::<u32>();
-		}
-
 		Ok(data)
 	}
 
-	pub fn read_frame(&mut self) -> Result<crate::frame::Frame, anyhow::Error> {
+	/// Read frame length and frame, but not the attached data of attachments, avatars and
+	/// stickers
+	fn read_frame_without_data(&mut self) -> Result<crate::frame::Frame, crate::error::Error> {
 		// read frame length from input file
-		let len: usize = self
-			.reader
-			.read_u32::<byteorder::BigEndian>()
-			.unwrap()
-			.try_into()
-			.unwrap();
+		// An EOF at this position means the backup was cut off at a frame boundary.
+		self.offset_frame = self.reader.position;
+		self.number_frame = self.count_frame;
+		let mut len = [0u8; std::mem::size_of::<u32>()];
+		match read_up_to(&mut self.reader, &mut len)? {
+			0 => {
+				return Err(crate::error::Error::MissingEnd {
+					frame: self.count_frame,
+					offset: self.offset_frame,
+				})
+			}
+			x if x < len.len() => {
+				return Err(crate::error::Error::Truncated {
+					frame: self.count_frame,
+					offset: self.offset_frame,
+				})
+			}
+			_ => (),
+		}
+
+		// Since backup version 1 the frame length is encrypted and part of the frame's MAC.
+		if self.version >= 1 {
+			self.decrypter.decrypt(&mut len);
+		}
+		let len: usize = byteorder::BigEndian::read_u32(&len).try_into().unwrap();
 		debug!(
 			"Read frame number {} with length of {} bytes",
 			self.count_frame, len
 		);
 
+		if len <= crate::decrypter::LENGTH_HMAC {
+			return Err(crate::error::Error::Corrupted {
+				frame: self.count_frame,
+				offset: self.offset_frame,
+				reason: format!("invalid frame length of {} bytes", len),
+			});
+		}
+		if len > self.limits.frame_length {
+			return Err(crate::error::Error::FrameTooLarge {
+				frame: self.count_frame,
+				offset: self.offset_frame,
+				length: len,
+				limit: self.limits.frame_length,
+			});
+		}
+
 		// create frame
-		let frame = self.read_data(len, false)?;
-		let mut frame: crate::frame::Frame = frame.try_into()?;
+		let frame = self.read_data(len)?;
+		let frame: crate::frame::Frame = frame.try_into()?;
 		debug!("Frame type: {}", &frame);
 
-		match frame {
-			crate::frame::Frame::Attachment { data_length, .. } => {
-				frame.set_data(self.read_data(data_length, true)?);
+		match frame.get_data_length() {
+			Some(x) if x as u64 > self.limits.attachment_length => {
+				return Err(crate::error::Error::AttachmentTooLarge {
+					frame: self.count_frame,
+					offset: self.offset_frame,
+					length: x as u64,
+					limit: self.limits.attachment_length,
+				})
 			}
-			crate::frame::Frame::Avatar { data_length, .. } => {
-				frame.set_data(self.read_data(data_length, true)?);
-			}
-			crate::frame::Frame::Sticker { data_length, .. } => {
-				frame.set_data(self.read_data(data_length, true)?);
-			}
-			crate::frame::Frame::Header { .. } => return Err(anyhow!("unexpected header found")),
 			_ => (),
+		}
+
+		if let crate::frame::Frame::Header { .. } = frame {
+			return Err(crate::error::Error::Corrupted {
+				frame: self.count_frame,
+				offset: self.offset_frame,
+				reason: "unexpected header found".to_string(),
+			});
+		}
+
+		Ok(frame)
+	}
+
+	/// Read next frame
+	///
+	/// The attached data of attachments, avatars and stickers is not read. It can be read with
+	/// `read_attachment` afterwards, otherwise it is skipped. If the first frame cannot be
+	/// decrypted, `Error::WrongPassword` is returned, also in recovery mode.
+	pub fn read_frame(&mut self) -> Result<crate::frame::Frame, crate::error::Error> {
+		// skip attached data of the last frame if it was not read
+		if self.pending_data.is_some() {
+			self.read_attachment(&mut std::io::sink())?;
+		}
+
+		let frame = loop {
+			let offset = self.reader.position;
+			self.reader.start_journal();
+			let frame = self.read_frame_without_data();
+			let journal = self.reader.take_journal();
+
+			match frame {
+				// A truncated frame may also be caused by a corrupted frame length, but there is
+				// nothing to recover after a missing end frame.
+				Ok(x) => break x,
+				// Recovering would scan the whole backup in vain with a wrong password.
+				Err(e) if self.count_frame == 1 && self.is_wrong_password(&e, &journal) => {
+					debug!("Could not decrypt first frame: {}", e);
+					return Err(crate::error::Error::WrongPassword);
+				}
+				Err(e) if self.recover && !matches!(e, crate::error::Error::MissingEnd { .. }) => {
+					warn!(
+						"Frame number {} at offset {} is corrupted: {}",
+						self.count_frame, offset, e
+					);
+					self.resync(offset, &journal)?;
+				}
+				Err(e) => return Err(e),
+			}
 		};
 
 		// clean up and return
+		self.pending_data = frame.get_data_length();
 		self.count_frame += 1;
 		Ok(frame)
 	}
 
+	/// Read attached data of the last frame and write it to `sink`
+	///
+	/// The data is decrypted in chunks of `CHUNK_SIZE` bytes, so memory usage does not depend on
+	/// the size of the data. The MAC can only be verified after all data has been written to
+	/// `sink`, so `sink` sees unverified data until this function returns. If the MAC does not
+	/// match, `Error::Mac` is returned and the written data has to be discarded. In recovery
+	/// mode, the frame is counted as lost and reading can continue with the next frame.
+	pub fn read_attachment<W: Write>(&mut self, sink: &mut W) -> Result<(), crate::error::Error> {
+		let length = self
+			.pending_data
+			.take()
+			.ok_or_else(|| crate::error::Error::MissingData("Last frame".to_string()))?;
+		let offset = self.reader.position;
+
+		// Attached data need an update of MAC with IV.
+		// Their given length corresponds to file length, so hmac data has to be added.
+		self.decrypter.mac_update_with_iv();
+
+		let mut chunk = vec![0u8; std::cmp::min(length, CHUNK_SIZE)];
+		let mut remaining = length;
+		while remaining > 0 {
+			let chunk = &mut chunk[..std::cmp::min(remaining, CHUNK_SIZE)];
+			self.read_exact(chunk)?;
+			self.decrypter.decrypt(chunk);
+			sink.write_all(chunk)
+				.context("Could not write attached data")?;
+			remaining -= chunk.len();
+		}
+
+		// read and verify hmac
+		let mut hmac = [0u8; crate::decrypter::LENGTH_HMAC];
+		self.read_exact(&mut hmac)?;
+		let ret = self.decrypter.verify_mac(&hmac);
+		self.decrypter.increase_iv();
+
+		if let Err(e) = ret {
+			if self.recover {
+				warn!(
+					"Data of frame number {} at offset {} is corrupted: {}",
+					self.number_frame, offset, e
+				);
+				self.count_lost_frame += 1;
+				self.count_lost_byte += length + crate::decrypter::LENGTH_HMAC;
+			}
+			return Err(crate::error::Error::Mac {
+				frame: self.number_frame,
+				offset,
+			});
+		}
+
+		Ok(())
+	}
+
+	/// Search for the next valid frame after a corrupted frame at `offset`
+	///
+	/// `journal` contains the bytes read of the corrupted frame. On success, the reader and the
+	/// decrypter are set to the start of the next valid frame.
+	fn resync(&mut self, offset: u64, journal: &[u8]) -> Result<(), crate::error::Error> {
+		// start again one byte after the corrupted frame
+		if !journal.is_empty() {
+			self.reader.unread(&journal[1..]);
+		}
+
+		// The counter of a frame is unknown after a corrupted region, so we test all counters
+		// of the next `RECOVER_MAX_LOST_FRAMES` frames. The first bytes of the key streams are
+		// enough to check whether a frame is plausible.
+		let counter = self.decrypter.get_counter();
+		let keystreams: Vec<Vec<u8>> = (0..RECOVER_MAX_LOST_FRAMES)
+			.map(|i| self.decrypter.keystream(counter.wrapping_add(i), 16))
+			.collect();
+
+		// Since backup version 1 the frame length is encrypted. The high bits of plausible
+		// lengths are zero, so only key streams whose high bits match those of the encrypted
+		// length are tested. This keeps the cost per skipped byte independent of the number of
+		// counters.
+		let shift = 32 - (RECOVER_MAX_FRAME_LENGTH as u32).leading_zeros();
+		let mut candidates: std::collections::HashMap<u32, Vec<usize>> =
+			std::collections::HashMap::new();
+		for (i, keystream) in keystreams.iter().enumerate() {
+			candidates
+				.entry(byteorder::BigEndian::read_u32(&keystream[..4]) >> shift)
+				.or_default()
+				.push(i);
+		}
+		let all: Vec<usize> = (0..keystreams.len()).collect();
+
+		loop {
+			let mut head = [0u8; 16];
+			let count = read_up_to(&mut self.reader, &mut head)?;
+			self.reader.unread(&head[..count]);
+
+			if count < std::mem::size_of::<u32>() + 2 {
+				warn!(
+					"No valid frame found after corrupted frame at offset {}",
+					offset
+				);
+				self.count_lost_byte += (self.reader.position - offset) as usize + count;
+				return Err(crate::error::Error::Truncated {
+					frame: self.count_frame,
+					offset,
+				});
+			}
+
+			// the frame length of old backups does not depend on the counter
+			if self.version == 0 {
+				let len: usize = byteorder::BigEndian::read_u32(&head[..4])
+					.try_into()
+					.unwrap();
+				if len <= crate::decrypter::LENGTH_HMAC || len > RECOVER_MAX_FRAME_LENGTH {
+					self.reader.read_exact(&mut head[..1])?;
+					continue;
+				}
+			}
+
+			let candidates: &[usize] = if self.version >= 1 {
+				candidates
+					.get(&(byteorder::BigEndian::read_u32(&head[..4]) >> shift))
+					.map_or(&[], |x| x)
+			} else {
+				&all
+			};
+
+			for &i in candidates {
+				let keystream = &keystreams[i];
+				// decrypt frame length and first bytes of frame
+				let (len, start) = if self.version >= 1 {
+					let mut len = [0u8; 4];
+					xor(&head[..4], &keystream[..4], &mut len);
+					let mut start = [0u8; 12];
+					xor(&head[4..count], &keystream[4..], &mut start);
+					(byteorder::BigEndian::read_u32(&len), start)
+				} else {
+					let mut start = [0u8; 12];
+					xor(&head[4..count], &keystream[..12], &mut start);
+					(byteorder::BigEndian::read_u32(&head[..4]), start)
+				};
+
+				let len: usize = len.try_into().unwrap();
+				if len <= crate::decrypter::LENGTH_HMAC
+					|| len > RECOVER_MAX_FRAME_LENGTH
+					|| !plausible_frame_start(
+						&start[..std::cmp::min(count - 4, 12)],
+						len - crate::decrypter::LENGTH_HMAC,
+					) {
+					continue;
+				}
+
+				// try to read the frame with this counter
+				let position = self.reader.position;
+				self.decrypter.set_counter(counter.wrapping_add(i as u32));
+				self.reader.start_journal();
+				let frame = self.read_frame_without_data();
+				let journal = self.reader.take_journal();
+
+				if frame.is_ok() {
+					self.reader.unread(&journal);
+					self.decrypter.set_counter(counter.wrapping_add(i as u32));
+					self.count_lost_frame += i;
+					self.count_lost_byte += (position - offset) as usize;
+					// keep the numbers of the following frames
+					self.count_frame += i;
+					warn!(
+						"Found next valid frame at offset {} ({} bytes and {} frames skipped)",
+						position,
+						position - offset,
+						i
+					);
+					return Ok(());
+				}
+				self.reader.unread(&journal);
+			}
+
+			// skip one byte
+			self.reader.read_exact(&mut head[..1])?;
+		}
+	}
+
+	/// Read exactly `buf.len()` bytes of the current frame
+	///
+	/// An EOF is reported as `crate::error::Error::Truncated`.
+	fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), crate::error::Error> {
+		self.reader.read_exact(buf).map_err(|e| {
+			if e.kind() == std::io::ErrorKind::UnexpectedEof {
+				crate::error::Error::Truncated {
+					frame: self.number_frame,
+					offset: self.offset_frame,
+				}
+			} else {
+				e.into()
+			}
+		})
+	}
+
+	/// Check whether error `e` of the first frame is caused by a wrong password
+	///
+	/// With a wrong password, the MAC of the first frame does not match or, since backup version
+	/// 1, its decrypted length is garbage. `journal` contains the bytes read of the frame. If the
+	/// backup ends within the frame, the password is only considered wrong if enough bytes are
+	/// left to see that the decrypted frame is implausible.
+	fn is_wrong_password(&mut self, e: &crate::error::Error, journal: &[u8]) -> bool {
+		let len_size = std::mem::size_of::<u32>();
+		match e {
+			crate::error::Error::Mac { .. } => true,
+			// only the frame length has been read if it is invalid
+			crate::error::Error::Corrupted { .. } | crate::error::Error::FrameTooLarge { .. } => {
+				self.version >= 1 && journal.len() == len_size
+			}
+			crate::error::Error::Truncated { .. } if self.version >= 1 => {
+				if journal.len() <= len_size {
+					return false;
+				}
+
+				let keystream = self
+					.decrypter
+					.keystream(self.decrypter.get_counter(), journal.len());
+				let mut data = vec![0u8; journal.len()];
+				xor(journal, &keystream, &mut data);
+				let len: usize = byteorder::BigEndian::read_u32(&data[..len_size])
+					.try_into()
+					.unwrap();
+				let start = &data[len_size..std::cmp::min(data.len(), len_size + 12)];
+
+				// a field number and its length take up to 6 bytes
+				let length = len.saturating_sub(crate::decrypter::LENGTH_HMAC);
+				(start.len() >= 6 || start.len() >= length) && !plausible_frame_start(start, length)
+			}
+			_ => false,
+		}
+	}
+
+	/// Number of frames read, including the header
 	pub fn get_count_frame(&self) -> usize {
 		self.count_frame
 	}
 
+	/// Number of bytes read from the backup
 	pub fn get_count_byte(&self) -> usize {
-		self.count_byte
+		self.reader.position.try_into().unwrap()
 	}
 
-	pub fn get_file_size(&self) -> u64 {
+	/// Total size of the backup in bytes, if known
+	pub fn get_file_size(&self) -> Option<u64> {
 		self.file_bytes
 	}
+
+	/// Keys used to decrypt the backup
+	pub fn get_key(&self) -> &crate::key::BackupKey {
+		&self.key
+	}
+
+	/// Backup version given in the header
+	pub fn get_version(&self) -> u32 {
+		self.version
+	}
+
+	/// Number of frames skipped in recovery mode
+	pub fn get_count_lost_frame(&self) -> usize {
+		self.count_lost_frame
+	}
+
+	/// Number of bytes skipped in recovery mode
+	pub fn get_count_lost_byte(&self) -> usize {
+		self.count_lost_byte
+	}
 }
 
-impl Iterator for InputFile {
-	type Item = Result<crate::frame::Frame, anyhow::Error>;
+impl<R: Read> Iterator for InputFile<R> {
+	type Item = Result<crate::frame::Frame, crate::error::Error>;
 
 	fn next(&mut self) -> Option<Self::Item> {
+		// stop after the end frame or an error
+		if self.finished {
+			return None;
+		}
+
 		let ret = self.read_frame();
 
 		if let Ok(x) = ret {
 			match x {
-				crate::frame::Frame::End => None,
+				crate::frame::Frame::End { .. } => {
+					self.finished = true;
+					None
+				}
 				_ => Some(Ok(x)),
 			}
 		} else {
+			self.finished = true;
 			Some(ret)
 		}
 	}
 }
+
+/// Read as many bytes as possible into `buf`, stopping only at EOF
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+	let mut count = 0;
+	while count < buf.len() {
+		match reader.read(&mut buf[count..]) {
+			Ok(0) => break,
+			Ok(x) => count += x,
+			Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(count)
+}
+
+fn xor(data: &[u8], keystream: &[u8], out: &mut [u8]) {
+	for ((o, d), k) in out.iter_mut().zip(data).zip(keystream) {
+		*o = d ^ k;
+	}
+}
+
+/// Check whether `start` may be the beginning of a serialized frame of `length` bytes
+///
+/// Every frame consists of exactly one field, which is either the `end` flag or a message
+/// filling the whole frame.
+fn plausible_frame_start(start: &[u8], length: usize) -> bool {
+	match start.first() {
+		// end flag
+		Some(0x30) => length == 2 && start.len() >= 2 && start[1] <= 1,
+		// length delimited field with a field number from 1 to 9
+		Some(x) if x & 0x07 == 2 && (1..=9).contains(&(x >> 3)) => {
+			let mut value: usize = 0;
+			for (i, b) in start.iter().skip(1).take(5).enumerate() {
+				value |= ((b & 0x7f) as usize) << (7 * i);
+				if b & 0x80 == 0 {
+					return 1 + (i + 1) + value == length;
+				}
+			}
+			false
+		}
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PASSWORD: &[u8] = b"123451234512345123451234512345";
+
+	/// Encode a backup with a version frame and `count` numbered statements
+	fn encode(version: u32, count: usize) -> Vec<u8> {
+		let mut encoder = crate::encoder::Encoder::new(Vec::new(), PASSWORD, version).unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Version {
+				version: 57,
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		for i in 0..count {
+			encoder
+				.write_frame(crate::frame::Frame::Statement {
+					statement: String::from("INSERT INTO sms VALUES (?, ?)"),
+					parameter: vec![
+						rusqlite::types::Value::Integer(i as i64),
+						rusqlite::types::Value::Text(String::from("hello world")),
+					],
+					unknown_fields: Default::default(),
+				})
+				.unwrap();
+		}
+		encoder.finish().unwrap()
+	}
+
+	fn open<'a>(
+		backup: &'a [u8],
+		password: &[u8],
+		recover: bool,
+	) -> Result<InputFile<std::io::Cursor<&'a [u8]>>, crate::error::Error> {
+		InputFile::new(
+			std::io::Cursor::new(backup),
+			None,
+			&crate::key::Secret::Password(zeroize::Zeroizing::new(password.to_vec())),
+			InputOptions {
+				recover,
+				..Default::default()
+			},
+		)
+	}
+
+	/// Return the numbers of all statements and the offset after each frame
+	fn decode(input: &mut InputFile<std::io::Cursor<&[u8]>>) -> (Vec<i64>, Vec<usize>) {
+		let mut numbers = Vec::new();
+		let mut offsets = Vec::new();
+		while let Some(frame) = input.next() {
+			if let crate::frame::Frame::Statement { parameter, .. } = frame.unwrap() {
+				match parameter[0] {
+					rusqlite::types::Value::Integer(x) => numbers.push(x),
+					_ => panic!("unexpected parameter"),
+				}
+			}
+			offsets.push(input.get_count_byte());
+		}
+		(numbers, offsets)
+	}
+
+	#[test]
+	fn recover() {
+		for version in 0..=crate::encoder::MAX_VERSION {
+			let mut backup = encode(version, 5);
+			let mut input = open(&backup, PASSWORD, false).unwrap();
+			let (_, offsets) = decode(&mut input);
+			let count_frame = input.get_count_frame();
+
+			// corrupt the statement with number 2
+			backup[(offsets[2] + offsets[3]) / 2] ^= 0xff;
+			assert!(open(&backup, PASSWORD, false).unwrap().any(|x| x.is_err()));
+
+			let mut input = open(&backup, PASSWORD, true).unwrap();
+			assert_eq!(decode(&mut input).0, vec![0, 1, 3, 4]);
+			assert_eq!(input.get_count_lost_frame(), 1);
+			assert_eq!(input.get_count_frame(), count_frame);
+		}
+	}
+
+	#[test]
+	fn corrupted_attachment() {
+		let mut encoder = crate::encoder::Encoder::new(Vec::new(), PASSWORD, 1).unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Version {
+				version: 57,
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Attachment {
+				data_length: 1000,
+				id: 1,
+				row: 1,
+				data: Some(Box::new(std::io::Cursor::new(vec![7u8; 1000]))),
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Version {
+				version: 58,
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		let mut backup = encoder.finish().unwrap();
+
+		// corrupt the attached data
+		let mut input = open(&backup, PASSWORD, false).unwrap();
+		input.nth(1).unwrap().unwrap();
+		let offset = input.get_count_byte();
+		backup[offset + 500] ^= 0xff;
+
+		for recover in [false, true] {
+			let mut input = open(&backup, PASSWORD, recover).unwrap();
+			input.nth(1).unwrap().unwrap();
+
+			// the data is passed on before the MAC is verified
+			let mut data = Vec::new();
+			assert!(matches!(
+				input.read_attachment(&mut data),
+				Err(crate::error::Error::Mac { frame: 2, .. })
+			));
+			assert_eq!(data.len(), 1000);
+
+			if recover {
+				assert_eq!(input.get_count_lost_frame(), 1);
+				assert!(matches!(
+					input.next(),
+					Some(Ok(crate::frame::Frame::Version { version: 58, .. }))
+				));
+			}
+		}
+	}
+
+	#[test]
+	fn truncated_first_frame() {
+		for version in 0..=crate::encoder::MAX_VERSION {
+			let backup = encode(version, 0);
+			let header = 4 + byteorder::BigEndian::read_u32(&backup[..4]) as usize;
+
+			// cut off within the frame length and within the frame
+			for length in [header + 2, header + 4 + 6] {
+				let mut input = open(&backup[..length], PASSWORD, false).unwrap();
+				assert!(matches!(
+					input.next(),
+					Some(Err(crate::error::Error::Truncated { .. }))
+				));
+			}
+		}
+
+		// a wrong password is still detected
+		let backup = encode(1, 0);
+		let header = 4 + byteorder::BigEndian::read_u32(&backup[..4]) as usize;
+		let mut input = open(
+			&backup[..header + 4 + 6],
+			b"000000000000000000000000000000",
+			false,
+		)
+		.unwrap();
+		assert!(matches!(
+			input.next(),
+			Some(Err(crate::error::Error::WrongPassword))
+		));
+	}
+
+	#[test]
+	fn recover_wrong_password() {
+		let backup = encode(1, 1);
+		let mut input = open(&backup, b"000000000000000000000000000000", true).unwrap();
+		assert!(matches!(
+			input.next(),
+			Some(Err(crate::error::Error::WrongPassword))
+		));
+	}
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+	use super::*;
+
+	const PASSWORD: &[u8] = b"123451234512345123451234512345";
+	const COUNT_STATEMENT: usize = 20_000;
+	const COUNT_ATTACHMENT: usize = 20;
+	const LENGTH_ATTACHMENT: usize = 100_000;
+
+	/// Decode a synthetic backup of statements and attachments
+	#[bench]
+	fn decode(b: &mut test::Bencher) {
+		let mut encoder = crate::encoder::Encoder::new(Vec::new(), PASSWORD, 1).unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Version {
+				version: 57,
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		for i in 0..COUNT_STATEMENT {
+			encoder
+				.write_frame(crate::frame::Frame::Statement {
+					statement: String::from("INSERT INTO sms VALUES (?, ?, ?)"),
+					parameter: vec![
+						rusqlite::types::Value::Integer(i as i64),
+						rusqlite::types::Value::Text(String::from("hello world")),
+						rusqlite::types::Value::Null,
+					],
+					unknown_fields: Default::default(),
+				})
+				.unwrap();
+		}
+		for i in 0..COUNT_ATTACHMENT {
+			encoder
+				.write_frame(crate::frame::Frame::Attachment {
+					data_length: LENGTH_ATTACHMENT,
+					id: i as u64,
+					row: i as u64,
+					data: Some(Box::new(std::io::Cursor::new(vec![7u8; LENGTH_ATTACHMENT]))),
+					unknown_fields: Default::default(),
+				})
+				.unwrap();
+		}
+		let backup = encoder.finish().unwrap();
+
+		// the keys are derived once, only decoding is measured
+		let secret = crate::key::Secret::Key(
+			InputFile::new(
+				std::io::Cursor::new(&backup),
+				None,
+				&crate::key::Secret::Password(zeroize::Zeroizing::new(PASSWORD.to_vec())),
+				Default::default(),
+			)
+			.unwrap()
+			.get_key()
+			.clone(),
+		);
+		b.bytes = backup.len() as u64;
+		b.iter(|| {
+			let mut input = InputFile::new(
+				std::io::Cursor::new(&backup),
+				None,
+				&secret,
+				Default::default(),
+			)
+			.unwrap();
+			let mut count = 0;
+			while let Some(frame) = input.next() {
+				if frame.unwrap().get_data_length().is_some() {
+					input.read_attachment(&mut std::io::sink()).unwrap();
+				}
+				count += 1;
+			}
+			assert_eq!(count, 1 + COUNT_STATEMENT + COUNT_ATTACHMENT);
+		});
+	}
+}
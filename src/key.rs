@@ -0,0 +1,230 @@
+use crate::error::Context;
+use sha2::Digest;
+use std::io::Write;
+use zeroize::Zeroizing;
+
+/// Section of key files
+const KEY_FILE_SECTION: &str = "signal-backup-key";
+
+/// Keys derived from backup password and salt
+///
+/// Deriving the keys is expensive, so they can be saved to and loaded from a key file. The
+/// SHA-256 fingerprint of the salt is stored with the keys to reject them for other backups.
+/// The keys are wiped from memory when they are dropped.
+#[derive(Clone)]
+pub struct BackupKey {
+	/// Key of the AES-256 encryption
+	pub cipher_key: Zeroizing<Vec<u8>>,
+	/// Key of the HMAC-SHA256 authentication
+	pub mac_key: Zeroizing<Vec<u8>>,
+	/// SHA-256 hash of the salt the keys were derived with
+	pub salt_fingerprint: Vec<u8>,
+}
+
+impl BackupKey {
+	/// Derive keys from `password` and `salt` of a backup
+	pub fn derive(password: &[u8], salt: &[u8]) -> Self {
+		let (cipher_key, mac_key) = crate::decrypter::derive_keys(password, salt);
+
+		Self {
+			cipher_key,
+			mac_key,
+			salt_fingerprint: fingerprint(salt),
+		}
+	}
+
+	/// Check whether the keys belong to a backup with the given `salt`
+	pub fn check_salt(&self, salt: &[u8]) -> Result<(), crate::error::Error> {
+		if self.salt_fingerprint != fingerprint(salt) {
+			return Err(crate::error::Error::WrongBackup {
+				fingerprint: self.salt_fingerprint.clone(),
+				expected: fingerprint(salt),
+			});
+		}
+
+		Ok(())
+	}
+
+	/// Load keys from key file at `path`
+	///
+	/// The key file is parsed in place, so no copies of the keys are left in memory.
+	pub fn read(path: &std::path::Path) -> Result<Self, crate::error::Error> {
+		let content = Zeroizing::new(
+			std::fs::read_to_string(path)
+				.with_context(|| format!("Could not read key file: {}", path.to_string_lossy()))?,
+		);
+
+		// collect values of our section
+		let mut section = None;
+		let mut has_section = false;
+		let mut values = Vec::new();
+		for line in content.lines().map(str::trim) {
+			if line.starts_with('[') && line.ends_with(']') {
+				section = Some(&line[1..line.len() - 1]);
+				has_section |= section == Some(KEY_FILE_SECTION);
+			} else if section == Some(KEY_FILE_SECTION) {
+				if let Some((name, value)) = line.split_once('=') {
+					values.push((name.trim(), value.trim()));
+				}
+			}
+		}
+		if !has_section {
+			return Err(crate::error::Error::Config(format!(
+				"Key file has no section [{}]",
+				KEY_FILE_SECTION
+			)));
+		}
+
+		let value =
+			|name: &str, length: usize| -> Result<Zeroizing<Vec<u8>>, crate::error::Error> {
+				let value = values.iter().find(|x| x.0 == name).ok_or_else(|| {
+					crate::error::Error::Config(format!("Key file has no value {}", name))
+				})?;
+				match from_hex(value.1) {
+					Some(x) if x.len() == length => Ok(x),
+					_ => Err(crate::error::Error::Config(format!(
+						"Key file has an invalid value {}",
+						name
+					))),
+				}
+			};
+
+		Ok(Self {
+			cipher_key: value("cipher_key", 32)?,
+			mac_key: value("mac_key", 32)?,
+			salt_fingerprint: value("salt_sha256", 32)?.to_vec(),
+		})
+	}
+
+	/// Save keys to key file at `path`, `-` writes to stdout
+	///
+	/// On unix, the key file is only readable by its owner.
+	pub fn write(&self, path: &std::path::Path) -> Result<(), crate::error::Error> {
+		// reserve enough space, so the buffer is never reallocated
+		let mut content = Zeroizing::new(String::with_capacity(256));
+		content.push_str(&format!("[{}]\n", KEY_FILE_SECTION));
+		for (name, value) in [
+			("salt_sha256", &self.salt_fingerprint[..]),
+			("cipher_key", &self.cipher_key[..]),
+			("mac_key", &self.mac_key[..]),
+		]
+		.iter()
+		{
+			content.push_str(name);
+			content.push('=');
+			content.push_str(&to_hex(value));
+			content.push('\n');
+		}
+
+		if path == std::path::Path::new("-") {
+			return std::io::stdout()
+				.write_all(content.as_bytes())
+				.context("Could not write key to stdout");
+		}
+
+		let mut options = std::fs::OpenOptions::new();
+		options.write(true).create_new(true);
+		#[cfg(unix)]
+		std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+		let mut file = options
+			.open(path)
+			.with_context(|| format!("Could not create key file: {}", path.to_string_lossy()))?;
+		file.write_all(content.as_bytes())
+			.with_context(|| format!("Could not write key file: {}", path.to_string_lossy()))
+	}
+}
+
+/// Secret needed to decrypt a backup
+pub enum Secret {
+	/// 30 digit backup password
+	Password(Zeroizing<Vec<u8>>),
+	/// Keys already derived from password and salt
+	Key(BackupKey),
+}
+
+impl Secret {
+	/// Secret of a 30 digit backup password, see `crate::password::parse`
+	pub fn from_password(password: &str) -> Result<Self, crate::error::Error> {
+		Ok(Self::Password(crate::password::parse(password)?))
+	}
+
+	/// Return keys for a backup with the given `salt`
+	///
+	/// Keys are derived from passwords, given keys are checked against `salt`.
+	pub fn key(&self, salt: &[u8]) -> Result<BackupKey, crate::error::Error> {
+		match self {
+			Self::Password(password) => Ok(BackupKey::derive(password, salt)),
+			Self::Key(key) => {
+				key.check_salt(salt)?;
+				Ok(key.clone())
+			}
+		}
+	}
+}
+
+fn fingerprint(salt: &[u8]) -> Vec<u8> {
+	sha2::Sha256::digest(salt).to_vec()
+}
+
+/// Format `data` as lowercase hex string
+pub fn to_hex(data: &[u8]) -> Zeroizing<String> {
+	let mut hex = Zeroizing::new(String::with_capacity(2 * data.len()));
+	for x in data {
+		hex.push(char::from_digit((x >> 4).into(), 16).unwrap());
+		hex.push(char::from_digit((x & 0x0f).into(), 16).unwrap());
+	}
+	hex
+}
+
+fn from_hex(data: &str) -> Option<Zeroizing<Vec<u8>>> {
+	let mut bytes = Zeroizing::new(Vec::with_capacity(data.len() / 2));
+	for x in data.as_bytes().chunks(2) {
+		match x {
+			[_, _] => bytes.push(u8::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok()?),
+			_ => return None,
+		}
+	}
+	Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn hex() {
+		assert_eq!(*to_hex(&[0, 15, 255]), "000fff");
+		assert_eq!(from_hex("000fFf").as_deref(), Some(&vec![0, 15, 255]));
+		assert!(from_hex("000").is_none());
+		assert!(from_hex("0g").is_none());
+	}
+
+	#[test]
+	fn salt_mismatch() {
+		let secret = Secret::Key(BackupKey {
+			cipher_key: Zeroizing::new(vec![0; 32]),
+			mac_key: Zeroizing::new(vec![0; 32]),
+			salt_fingerprint: fingerprint(&[1; 32]),
+		});
+		assert!(secret.key(&[1; 32]).is_ok());
+		assert!(secret.key(&[2; 32]).is_err());
+	}
+
+	#[test]
+	fn key_file() {
+		let key = BackupKey {
+			cipher_key: Zeroizing::new(vec![1; 32]),
+			mac_key: Zeroizing::new(vec![2; 32]),
+			salt_fingerprint: fingerprint(&[3; 32]),
+		};
+		let path = std::env::temp_dir().join(format!("signal-backup-key-{}", std::process::id()));
+		key.write(&path).unwrap();
+		let read = BackupKey::read(&path);
+		std::fs::remove_file(&path).unwrap();
+
+		let read = read.unwrap();
+		assert_eq!(read.cipher_key, key.cipher_key);
+		assert_eq!(read.mac_key, key.mac_key);
+		assert_eq!(read.salt_fingerprint, key.salt_fingerprint);
+	}
+}
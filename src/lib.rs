@@ -0,0 +1,90 @@
+//! Decode Signal backups
+//!
+//! A backup is opened with `InputFile`, which decrypts it frame by frame. Frames can be
+//! inspected directly or passed to one of the outputs implementing `SignalOutput`:
+//!
+//! ```no_run
+//! use signal_backup_decode::output::SignalOutput;
+//!
+//! # fn main() -> Result<(), signal_backup_decode::Error> {
+//! let secret = signal_backup_decode::Secret::from_password("12345 12345 12345 12345 12345 12345")?;
+//! let mut reader = signal_backup_decode::InputFile::open(
+//!     std::path::Path::new("signal.backup"),
+//!     &secret,
+//!     signal_backup_decode::InputOptions::default(),
+//! )?;
+//! let mut output = signal_backup_decode::output_none::SignalOutputNone::new();
+//!
+//! while let Some(frame) = reader.next() {
+//!     let mut frame = frame?;
+//!
+//!     // attached data has to be read before the next frame
+//!     if frame.get_data_length().is_some() {
+//!         let mut data = Vec::new();
+//!         reader.read_attachment(&mut data)?;
+//!         frame.set_data(Box::new(std::io::Cursor::new(data)));
+//!     }
+//!
+//!     output.write_frame(frame)?;
+//! }
+//! output.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! To avoid keeping attached data in memory, it can be streamed to an output running in another
+//! thread with `pipe::pipe`.
+
+#![warn(missing_docs)]
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
+
+#[cfg(all(test, feature = "bench"))]
+extern crate test;
+
+#[allow(non_snake_case)]
+mod Backups;
+mod cipher;
+mod decrypter;
+/// Write encrypted backups
+pub mod encoder;
+mod encrypter;
+/// Errors of this crate
+pub mod error;
+/// Frames of backups
+pub mod frame;
+/// Read and decrypt backups
+pub mod input;
+/// Passwords and keys of backups
+pub mod key;
+mod message;
+/// Common interface of the outputs
+pub mod output;
+/// Output to a new encrypted backup
+pub mod output_backup;
+/// Output to csv files
+pub mod output_csv;
+/// Output filtering messages before passing them on
+pub mod output_filter;
+/// Output to JSON lines
+pub mod output_jsonl;
+/// Output discarding all frames
+pub mod output_none;
+/// Output to a database and files
+pub mod output_raw;
+/// Output to several outputs at once
+pub mod output_tee;
+/// Backup passwords and recovery of partially known passwords
+pub mod password;
+/// Stream attached data between threads
+pub mod pipe;
+mod schema;
+/// Content addressed file store
+pub mod store;
+#[cfg(test)]
+mod temp_dir;
+
+pub use error::Error;
+pub use frame::Frame;
+pub use input::{InputFile, InputOptions, Limits};
+pub use key::{BackupKey, Secret};
+pub use output::SignalOutput;
@@ -1,38 +1,104 @@
-use log::error;
+use log::{error, info, warn};
+use signal_backup_decode::error::Context;
+use signal_backup_decode::{
+	error, input, key, output, output_backup, output_csv, output_filter, output_jsonl, output_none,
+	output_raw, output_tee, password, pipe,
+};
 use std::convert::TryInto;
 
-mod Backups;
 mod args;
-mod decrypter;
 mod display;
-mod frame;
-mod input;
-mod message;
-mod output;
-mod output_csv;
-mod output_none;
-mod output_raw;
-
-fn run(config: &args::Config) -> Result<(), anyhow::Error> {
-	// output
-	let mut output: Box<dyn crate::output::SignalOutput> = match config.output_type {
-		crate::output::SignalOutputType::None => {
-			Box::new(crate::output_none::SignalOutputNone::new())
-		}
-		crate::output::SignalOutputType::Raw => Box::new(crate::output_raw::SignalOutputRaw::new(
-			&config.path_output,
-			config.force_overwrite,
-			config.output_raw_db_in_memory,
-		)?),
-		crate::output::SignalOutputType::Csv => Box::new(crate::output_csv::SignalOutputCsv::new(
-			&config.path_output,
-			config.force_overwrite,
-		)?),
-	};
+
+/// Exit code of errors not listed below, e.g. invalid arguments or failed file operations
+const EXIT_ERROR: i32 = 1;
+/// Exit code if the password or key does not match the backup
+const EXIT_WRONG_PASSWORD: i32 = 2;
+/// Exit code if the MAC of a frame does not match
+const EXIT_MAC: i32 = 3;
+/// Exit code if the backup is cut off before its end
+const EXIT_TRUNCATED: i32 = 4;
+/// Exit code if a frame could not be parsed or is implausible
+const EXIT_CORRUPTED: i32 = 5;
+/// Exit code if a frame or its attached data exceeds the limits
+const EXIT_TOO_LARGE: i32 = 6;
+/// Exit code if a frame is not supported
+const EXIT_UNSUPPORTED_FRAME: i32 = 7;
+/// Exit code if writing the database failed
+const EXIT_SQL: i32 = 8;
+/// Exit code if an output file already exists
+const EXIT_OUTPUT_CONFLICT: i32 = 9;
+
+fn run(config: &args::Config) -> Result<(), error::Error> {
+	// recover password and exit
+	if let Some(ref pattern) = config.password_pattern {
+		let prefix = password::read_prefix(&config.path_input)?;
+		let threads = std::thread::available_parallelism().map_or(1, |x| x.get());
+		// don't print the progress bar if it is overwritten by debug messages or if only
+		// warnings and errors are requested
+		let hidden = config.log_level == log::Level::Debug || config.log_level < log::Level::Info;
+		return match password::recover(&prefix, pattern, config.input_limits, threads, hidden)? {
+			Some(x) => {
+				info!("Password found");
+				println!("{}", password::format(&x));
+				Ok(())
+			}
+			None => Err(error::Error::PasswordNotFound),
+		};
+	}
+	let secret = config.secret.as_ref().unwrap();
+
+	// only check the password with the first frame and exit
+	if config.check_password {
+		check_password(config, secret)?;
+		info!("Password is correct");
+		return Ok(());
+	}
 
 	// input
-	let mut reader =
-		input::InputFile::new(&config.path_input, &config.password, config.verify_mac)?;
+	let mut reader = input::InputFile::open(
+		&config.path_input,
+		secret,
+		input::InputOptions {
+			verify_mac: config.verify_mac,
+			recover: config.recover,
+			limits: config.input_limits,
+		},
+	)?;
+	let recover = config.recover;
+	let allow_truncated = config.allow_truncated;
+
+	// export key after checking it with the first frame
+	if let Some(ref path) = config.path_export_key {
+		reader
+			.read_frame()
+			.context("Could not decrypt first frame, the key is not exported")?;
+		reader.get_key().write(path)?;
+		info!("Key exported to {}", path.to_string_lossy());
+		return Ok(());
+	}
+
+	// output
+	let mut outputs = config
+		.outputs
+		.iter()
+		.map(|x| {
+			Ok((
+				format!("{} ({})", x.output_type, x.path.to_string_lossy()),
+				open_output(config, x, secret, reader.get_version())?,
+			))
+		})
+		.collect::<Result<Vec<_>, error::Error>>()?;
+	let mut output: Box<dyn output::SignalOutput> = if outputs.len() == 1 {
+		outputs.pop().unwrap().1
+	} else {
+		Box::new(output_tee::SignalOutputTee::new(outputs))
+	};
+	if let Some(ref filter) = config.filter {
+		output = Box::new(output_filter::SignalOutputFilter::new(
+			output,
+			filter.clone(),
+		));
+	}
 
 	// progress bar
 	let progress = display::Progress::new(
@@ -50,40 +116,102 @@ fn run(config: &args::Config) -> Result<(), anyhow::Error> {
 	// and to display correct status
 	let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel(10);
 
-	let thread_input = std::thread::spawn(move || -> Result<(), anyhow::Error> {
+	// the output is only finished if the input could be read successfully
+	let input_failed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+	let input_failed_output = input_failed.clone();
+
+	let thread_input = std::thread::spawn(move || -> Result<(), error::Error> {
 		// we have to use a while let loop here because we want to access the reader object
 		// in the loop. This does not work with a simple for loop.
 		#[allow(clippy::while_let_on_iterator)]
 		while let Some(frame) = reader.next() {
 			match frame {
-				Ok(x) => {
+				Ok(mut x) => {
+					// attached data is streamed to the output thread through a pipe
+					let (pipe_writer, pipe_failed) = if x.get_data_length().is_some() {
+						let (pipe_writer, pipe_reader) = pipe::pipe();
+						let pipe_failed = pipe_reader.failed();
+						x.set_data(Box::new(pipe_reader));
+						(Some(pipe_writer), Some(pipe_failed))
+					} else {
+						(None, None)
+					};
+
 					// if we cannot send a frame, probably an error has occured in the
 					// output thread. Thus, just shut down the input thread. We will print
 					// the error in the output thread.
-					if frame_tx.send(x).is_err() {
+					if frame_tx.send((x, pipe_failed)).is_err() {
 						break;
 					}
 
+					if let Some(mut pipe_writer) = pipe_writer {
+						if let Err(e) = reader.read_attachment(&mut pipe_writer) {
+							// the output must not keep the data written so far
+							pipe_writer.fail(&e);
+
+							// corrupted data is skipped in recovery mode
+							if recover && matches!(e, error::Error::Mac { .. }) {
+								continue;
+							}
+
+							progress_read.finish_bytes();
+							if recover {
+								report_lost(&reader);
+							}
+
+							// keep everything read before the cut if requested
+							if allow_truncated && e.is_truncated() {
+								warn!("{}. Output contains all frames read before.", e);
+								return Ok(());
+							}
+
+							input_failed.store(true, std::sync::atomic::Ordering::SeqCst);
+							return Err(e);
+						}
+					}
+
 					// forward progress bar if everything is ok
 					progress_read.set_read_frames(reader.get_count_frame().try_into().unwrap());
 					progress_read.set_read_bytes(reader.get_count_byte().try_into().unwrap());
 				}
 				Err(e) => {
 					progress_read.finish_bytes();
+					if recover {
+						report_lost(&reader);
+					}
+
+					// keep everything read before the cut if requested
+					if allow_truncated && e.is_truncated() {
+						warn!("{}. Output contains all frames read before.", e);
+						return Ok(());
+					}
+
+					input_failed.store(true, std::sync::atomic::Ordering::SeqCst);
 					return Err(e);
 				}
 			}
 		}
 
 		progress_read.finish_bytes();
+		if recover {
+			report_lost(&reader);
+		}
 		Ok(())
 	});
 
-	let thread_output = std::thread::spawn(move || -> Result<(), anyhow::Error> {
-		for received in frame_rx {
+	let thread_output = std::thread::spawn(move || -> Result<(), error::Error> {
+		for (received, pipe_failed) in frame_rx {
 			match output.write_frame(received) {
 				Ok(_) => progress_write
 					.set_written_frames(output.get_written_frames().try_into().unwrap()),
+				// the input has already reported the corrupted or truncated data
+				Err(e)
+					if (recover || allow_truncated)
+						&& pipe_failed
+							.map_or(false, |x| x.load(std::sync::atomic::Ordering::SeqCst)) =>
+				{
+					info!("Frame with unreadable data skipped: {}", e)
+				}
 				Err(e) => {
 					progress_write.finish_frames();
 					return Err(e);
@@ -92,37 +220,275 @@ fn run(config: &args::Config) -> Result<(), anyhow::Error> {
 		}
 
 		progress_write.finish_frames();
+
+		// the input thread has finished when the channel is closed
+		if input_failed_output.load(std::sync::atomic::Ordering::SeqCst) {
+			warn!("Output is not finished due to an error while reading the backup");
+			return Ok(());
+		}
+
 		output.finish()?;
 		Ok(())
 	});
 
 	progress.finish_multi();
-	if let Err(e) = thread_input.join().unwrap() {
-		error!("{:#}.", e);
-	}
-	if let Err(e) = thread_output.join().unwrap() {
-		error!("{:#}.", e);
+	let result_input = thread_input.join().unwrap();
+	let result_output = thread_output.join().unwrap();
+
+	match (result_input, result_output) {
+		(Err(e_input), Err(e_output)) => {
+			error!("{}.", e_output);
+			Err(e_input)
+		}
+		(Err(e), _) | (_, Err(e)) => Err(e),
+		_ => Ok(()),
 	}
+}
 
+/// Open output `output`
+///
+/// `version` is the version of the input backup, it is kept by output type BACKUP.
+fn open_output(
+	config: &args::Config,
+	output: &args::Output,
+	secret: &key::Secret,
+	version: u32,
+) -> Result<Box<dyn output::SignalOutput>, error::Error> {
+	Ok(match output.output_type {
+		output::SignalOutputType::None => Box::new(output_none::SignalOutputNone::new()),
+		output::SignalOutputType::Raw => Box::new(output_raw::SignalOutputRaw::new(
+			&output.path,
+			config.force_overwrite,
+			config.output_raw_db_in_memory,
+			config.path_store.as_deref(),
+			config.output_raw_key_value_json,
+			config.output_raw_preference_format,
+		)?),
+		output::SignalOutputType::Csv => Box::new(output_csv::SignalOutputCsv::new(
+			&output.path,
+			config.force_overwrite,
+		)?),
+		output::SignalOutputType::Jsonl => Box::new(output_jsonl::SignalOutputJsonl::new(
+			&output.path,
+			config.force_overwrite,
+		)?),
+		output::SignalOutputType::Backup => Box::new(output_backup::SignalOutputBackup::new(
+			&output.path,
+			config.force_overwrite,
+			match (&config.new_password, secret) {
+				(Some(x), _) | (None, key::Secret::Password(x)) => x,
+				_ => {
+					return Err(error::Error::Config(
+						"no password given for the new backup".to_string(),
+					))
+				}
+			},
+			version,
+		)?),
+	})
+}
+
+/// Check whether `secret` decrypts the first frame of the input
+///
+/// Only the header and the first frame are read. Returns `error::Error::WrongPassword` if the
+/// frame cannot be decrypted, other errors (e.g. an unreadable file or an invalid header) are
+/// returned as they are.
+fn check_password(config: &args::Config, secret: &key::Secret) -> Result<(), error::Error> {
+	let mut reader = input::InputFile::open(
+		&config.path_input,
+		secret,
+		input::InputOptions {
+			limits: config.input_limits,
+			..Default::default()
+		},
+	)?;
+	reader.read_frame()?;
 	Ok(())
 }
 
+/// Report frames and bytes skipped in recovery mode
+fn report_lost<R: std::io::Read>(reader: &input::InputFile<R>) {
+	if reader.get_count_lost_frame() > 0 || reader.get_count_lost_byte() > 0 {
+		warn!(
+			"Recovery: {} frames and {} bytes lost due to corruption",
+			reader.get_count_lost_frame(),
+			reader.get_count_lost_byte()
+		);
+	} else {
+		info!("Recovery: no corrupted frames found");
+	}
+}
+
+/// Map `error` to the exit code of the process
+fn exit_code(error: &error::Error) -> i32 {
+	match error {
+		error::Error::WrongPassword
+		| error::Error::WrongBackup { .. }
+		| error::Error::PasswordNotFound => EXIT_WRONG_PASSWORD,
+		error::Error::Mac { .. } => EXIT_MAC,
+		error::Error::MissingEnd { .. } | error::Error::Truncated { .. } => EXIT_TRUNCATED,
+		error::Error::Corrupted { .. } | error::Error::Protobuf { .. } => EXIT_CORRUPTED,
+		error::Error::FrameTooLarge { .. } | error::Error::AttachmentTooLarge { .. } => {
+			EXIT_TOO_LARGE
+		}
+		error::Error::UnsupportedFrame(_) => EXIT_UNSUPPORTED_FRAME,
+		error::Error::Sql { .. } => EXIT_SQL,
+		error::Error::OutputConflict(_) => EXIT_OUTPUT_CONFLICT,
+		error::Error::MissingData(_)
+		| error::Error::Csv { .. }
+		| error::Error::Output(_)
+		| error::Error::Config(_)
+		| error::Error::Io { .. } => EXIT_ERROR,
+	}
+}
+
 fn main() {
 	// build config structure
 	let config = args::Config::new().unwrap_or_else(|e| {
 		eprintln!("Problem parsing arguments: {}.", e);
-		std::process::exit(1);
+		std::process::exit(exit_code(&e));
 	});
 
+	// keep stdout free for output written to it
+	let terminal_mode = if config
+		.outputs
+		.iter()
+		.any(|x| x.path == std::path::Path::new("-"))
+	{
+		simplelog::TerminalMode::Stderr
+	} else {
+		simplelog::TerminalMode::Mixed
+	};
 	simplelog::TermLogger::init(
 		config.log_level,
 		simplelog::Config::default(),
-		simplelog::TerminalMode::Mixed,
+		terminal_mode,
 	)
 	.unwrap();
 
 	if let Err(e) = run(&config) {
-		error!("{:#}.", e);
-		std::process::exit(1);
+		error!("{}.", e);
+		let code = exit_code(&e);
+
+		// wipe secrets before exiting
+		drop(config);
+		std::process::exit(code);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PASSWORD: &str = "123451234512345123451234512345";
+
+	/// Temporary directory which is removed with its content when dropped
+	struct TempDir(std::path::PathBuf);
+
+	impl TempDir {
+		fn new(name: &str) -> Self {
+			let path = std::env::temp_dir().join(format!(
+				"signal-backup-decode-test-{}-{}",
+				name,
+				std::process::id()
+			));
+			let _ = std::fs::remove_dir_all(&path);
+			std::fs::create_dir_all(&path).unwrap();
+			Self(path)
+		}
+	}
+
+	impl Drop for TempDir {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.0);
+		}
+	}
+
+	/// Encode a backup with a table row followed by an attachment and cut it within the
+	/// attached data
+	fn truncated_backup() -> Vec<u8> {
+		let mut encoder =
+			signal_backup_decode::encoder::Encoder::new(Vec::new(), PASSWORD.as_bytes(), 1)
+				.unwrap();
+		let frames = vec![
+			signal_backup_decode::Frame::Version {
+				version: 57,
+				unknown_fields: Default::default(),
+			},
+			signal_backup_decode::Frame::Statement {
+				statement: "CREATE TABLE t (v)".to_string(),
+				parameter: Vec::new(),
+				unknown_fields: Default::default(),
+			},
+			signal_backup_decode::Frame::Statement {
+				statement: "INSERT INTO t VALUES (?)".to_string(),
+				parameter: vec![rusqlite::types::Value::Integer(1)],
+				unknown_fields: Default::default(),
+			},
+			signal_backup_decode::Frame::Attachment {
+				data_length: 1000,
+				id: 1,
+				row: 1,
+				data: Some(Box::new(std::io::Cursor::new(vec![7u8; 1000]))),
+				unknown_fields: Default::default(),
+			},
+		];
+		for frame in frames {
+			encoder.write_frame(frame).unwrap();
+		}
+		let backup = encoder.finish().unwrap();
+
+		// find the start of the attached data
+		let mut input = input::InputFile::new(
+			std::io::Cursor::new(&backup),
+			None,
+			&key::Secret::Password(zeroize::Zeroizing::new(PASSWORD.as_bytes().to_vec())),
+			Default::default(),
+		)
+		.unwrap();
+		input.nth(3).unwrap().unwrap();
+		let length = input.get_count_byte() + 500;
+
+		backup[..length].to_vec()
+	}
+
+	#[test]
+	fn truncated_attachment() {
+		let dir = TempDir::new("main-truncated");
+		let path_backup = dir.0.join("signal.backup");
+		std::fs::write(&path_backup, truncated_backup()).unwrap();
+
+		let config = |output: &str, allow_truncated: bool| {
+			let mut args = vec![
+				"signal-backup-decode".into(),
+				path_backup.clone().into_os_string(),
+				"-p".into(),
+				PASSWORD.into(),
+				"-t".into(),
+				"RAW".into(),
+				"-o".into(),
+				dir.0.join(output).into_os_string(),
+			];
+			if allow_truncated {
+				args.push("--allow-truncated".into());
+			}
+			args::Config::from_args::<_, std::ffi::OsString>(args).unwrap()
+		};
+
+		// the output is not finished by default
+		assert!(matches!(
+			run(&config("strict", false)),
+			Err(error::Error::Truncated { .. })
+		));
+		assert!(!dir.0.join("strict").join("signal_backup.db").exists());
+
+		// all frames before the cut are written
+		run(&config("allowed", true)).unwrap();
+		let connection =
+			rusqlite::Connection::open(dir.0.join("allowed").join("signal_backup.db")).unwrap();
+		let count: i64 = connection
+			.query_row("SELECT count(*) FROM t", rusqlite::NO_PARAMS, |x| x.get(0))
+			.unwrap();
+		assert_eq!(count, 1);
 	}
 }
@@ -1,63 +1,202 @@
-use anyhow::anyhow;
+use crate::error::Context;
 
 /// Trait that defines common ouptut functions
+///
+/// Outputs receive the frames of a backup in order through `write_frame` and are closed with
+/// `finish`. The functions for single frame types create the frame and pass it to
+/// `write_frame`. Outputs handling each frame type separately implement these functions and
+/// `write_frame` with `write_frame_by_type`.
 pub trait SignalOutput: Send {
+	/// Write `frame`
+	///
+	/// Attached data of attachments, avatars and stickers has to be set.
+	fn write_frame(&mut self, frame: crate::frame::Frame) -> Result<(), crate::error::Error>;
+
+	/// Write SQL statement with its bound parameters
 	fn write_statement(
 		&mut self,
 		statement: &str,
 		parameters: &[rusqlite::types::Value],
-	) -> Result<(), anyhow::Error>;
+	) -> Result<(), crate::error::Error> {
+		self.write_frame(crate::frame::Frame::Statement {
+			statement: statement.to_string(),
+			parameter: parameters.to_vec(),
+			unknown_fields: Default::default(),
+		})
+	}
 
+	/// Write attachment
+	///
+	/// `data` streams the decrypted attachment. Its MAC is verified when the end of `data` is
+	/// reached, a read error is returned if the verification fails. By default, `data` is read
+	/// into memory before it is passed to `write_frame`.
 	fn write_attachment(
 		&mut self,
-		data: &[u8],
+		data: &mut dyn std::io::Read,
 		attachmend_id: u64,
 		row_id: u64,
-	) -> Result<(), anyhow::Error>;
+	) -> Result<(), crate::error::Error> {
+		let data = read_data(data)?;
+		self.write_frame(crate::frame::Frame::Attachment {
+			data_length: data.len(),
+			id: attachmend_id,
+			row: row_id,
+			data: Some(Box::new(std::io::Cursor::new(data))),
+			unknown_fields: Default::default(),
+		})
+	}
 
-	fn write_sticker(&mut self, data: &[u8], row_id: u64) -> Result<(), anyhow::Error>;
+	/// Write sticker image `data` of sticker row `row_id`
+	///
+	/// By default, `data` is read into memory before it is passed to `write_frame`.
+	fn write_sticker(
+		&mut self,
+		data: &mut dyn std::io::Read,
+		row_id: u64,
+	) -> Result<(), crate::error::Error> {
+		let data = read_data(data)?;
+		self.write_frame(crate::frame::Frame::Sticker {
+			data_length: data.len(),
+			row: row_id,
+			data: Some(Box::new(std::io::Cursor::new(data))),
+			unknown_fields: Default::default(),
+		})
+	}
 
-	fn write_avatar(&mut self, data: &[u8], name: &str) -> Result<(), anyhow::Error>;
+	/// Write avatar image `data` of contact or group `name`
+	///
+	/// By default, `data` is read into memory before it is passed to `write_frame`.
+	fn write_avatar(
+		&mut self,
+		data: &mut dyn std::io::Read,
+		name: &str,
+	) -> Result<(), crate::error::Error> {
+		let data = read_data(data)?;
+		self.write_frame(crate::frame::Frame::Avatar {
+			data_length: data.len(),
+			name: name.to_string(),
+			recipient_id: String::new(),
+			data: Some(Box::new(std::io::Cursor::new(data))),
+			unknown_fields: Default::default(),
+		})
+	}
 
+	/// Write Android shared preference
 	fn write_preference(
 		&mut self,
 		pref: &crate::Backups::SharedPreference,
-	) -> Result<(), anyhow::Error>;
+	) -> Result<(), crate::error::Error> {
+		self.write_frame(crate::frame::Frame::Preference {
+			preference: pref.clone(),
+			unknown_fields: Default::default(),
+		})
+	}
+
+	/// Write database version
+	fn write_version(&mut self, version: u32) -> Result<(), crate::error::Error> {
+		self.write_frame(crate::frame::Frame::Version {
+			version,
+			unknown_fields: Default::default(),
+		})
+	}
 
-	fn write_version(&mut self, version: u32) -> Result<(), anyhow::Error>;
+	/// Write entry of the key value store
+	fn write_key_value(
+		&mut self,
+		key_value: &crate::Backups::KeyValue,
+	) -> Result<(), crate::error::Error> {
+		self.write_frame(crate::frame::Frame::KeyValue {
+			key_value: key_value.clone(),
+			unknown_fields: Default::default(),
+		})
+	}
 
-	fn write_key_value(&mut self, key_value: &crate::Backups::KeyValue) ->  Result<(), anyhow::Error>;
+	/// Write frame unknown to this version
+	///
+	/// `data` is the serialized frame and `fields` are the numbers of its fields.
+	fn write_unknown(&mut self, data: &[u8], fields: &[u32]) -> Result<(), crate::error::Error> {
+		self.write_frame(crate::frame::Frame::Unknown {
+			data: data.to_vec(),
+			fields: fields.to_vec(),
+		})
+	}
 
+	/// Number of frames written
 	fn get_written_frames(&self) -> usize;
 
-	fn write_frame(&mut self, frame: crate::frame::Frame) -> Result<(), anyhow::Error> {
-		match frame {
-			crate::frame::Frame::Statement {
-				statement,
-				parameter,
-			} => self.write_statement(&statement, &parameter),
-			crate::frame::Frame::Preference { preference } => self.write_preference(&preference),
-			crate::frame::Frame::Attachment { id, row, data, .. } => {
-				self.write_attachment(data.as_ref().unwrap(), id, row)
-			}
-			crate::frame::Frame::Avatar { name, data, .. } => {
-				self.write_avatar(data.as_ref().unwrap(), &name)
-			}
-			crate::frame::Frame::Sticker { row, data, .. } => {
-				self.write_sticker(data.as_ref().unwrap(), row)
-			}
-			crate::frame::Frame::Version { version } => self.write_version(version),
-			crate::frame::Frame::KeyValue { key_value } => self.write_key_value(&key_value),
-			_ => Err(anyhow!("unexpected frame found")),
+	/// Finish writing, e.g. flush files and close the database
+	fn finish(&mut self) -> Result<(), crate::error::Error>;
+}
+
+/// Write `frame` with the function of `output` matching its type
+///
+/// This implements `SignalOutput::write_frame` for outputs implementing the functions of all
+/// frame types, their defaults would pass the frame back to `write_frame`. Attached data has to
+/// be set. Header and end frames are not written and return an error.
+pub fn write_frame_by_type<O: SignalOutput + ?Sized>(
+	output: &mut O,
+	frame: crate::frame::Frame,
+) -> Result<(), crate::error::Error> {
+	match frame {
+		crate::frame::Frame::Statement {
+			statement,
+			parameter,
+			..
+		} => output.write_statement(&statement, &parameter),
+		crate::frame::Frame::Preference { preference, .. } => output.write_preference(&preference),
+		crate::frame::Frame::Attachment { id, row, data, .. } => {
+			let mut data =
+				data.ok_or_else(|| crate::error::Error::MissingData("Attachment".to_string()))?;
+			output.write_attachment(&mut data, id, row)
+		}
+		crate::frame::Frame::Avatar { name, data, .. } => {
+			let mut data =
+				data.ok_or_else(|| crate::error::Error::MissingData("Avatar".to_string()))?;
+			output.write_avatar(&mut data, &name)
 		}
+		crate::frame::Frame::Sticker { row, data, .. } => {
+			let mut data =
+				data.ok_or_else(|| crate::error::Error::MissingData("Sticker".to_string()))?;
+			output.write_sticker(&mut data, row)
+		}
+		crate::frame::Frame::Version { version, .. } => output.write_version(version),
+		crate::frame::Frame::KeyValue { key_value, .. } => output.write_key_value(&key_value),
+		crate::frame::Frame::Unknown { data, fields } => output.write_unknown(&data, &fields),
+		x => Err(crate::error::Error::UnsupportedFrame(x.to_string())),
 	}
+}
 
-	fn finish(&mut self) -> Result<(), anyhow::Error>;
+/// Read attached data passed to a function of a single frame type
+fn read_data(data: &mut dyn std::io::Read) -> Result<Vec<u8>, crate::error::Error> {
+	let mut buffer = Vec::new();
+	data.read_to_end(&mut buffer)
+		.context("Could not read attached data")?;
+	Ok(buffer)
 }
 
 /// Defined output types
 pub enum SignalOutputType {
+	/// Only decrypt and verify, see `output_none`
 	None,
+	/// Database and files, see `output_raw`
 	Raw,
+	/// Csv output, see `output_csv`
 	Csv,
+	/// Encrypted backup, see `output_backup`
+	Backup,
+	/// Frames as JSON lines, see `output_jsonl`
+	Jsonl,
+}
+
+impl std::fmt::Display for SignalOutputType {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let name = match self {
+			Self::None => "NONE",
+			Self::Raw => "RAW",
+			Self::Csv => "CSV",
+			Self::Backup => "BACKUP",
+			Self::Jsonl => "JSONL",
+		};
+		write!(f, "{}", name)
+	}
 }
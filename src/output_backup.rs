@@ -0,0 +1,248 @@
+use crate::error::Context;
+use log::info;
+
+/// Write encrypted backup
+///
+/// This output module writes a new backup file with a fresh salt and iv. All frames are
+/// passed unchanged to `crate::encoder::Encoder`, including their unknown protobuf fields. The
+/// header and end frame are written by the encoder, unknown fields of the input's header and
+/// end frame are not kept.
+pub struct SignalOutputBackup {
+	encoder: Option<crate::encoder::Encoder<std::io::BufWriter<std::fs::File>>>,
+	written_frames: usize,
+}
+
+impl SignalOutputBackup {
+	/// Creates new output object
+	///
+	/// The backup is written to file `path` with the given backup `version`. `force_write`
+	/// determines whether an existing file will be overwritten.
+	pub fn new(
+		path: &std::path::Path,
+		force_write: bool,
+		password: &[u8],
+		version: u32,
+	) -> Result<Self, crate::error::Error> {
+		info!("Output file: {}", &path.to_string_lossy());
+
+		if path.exists() && !force_write {
+			return Err(crate::error::Error::OutputConflict(path.to_path_buf()));
+		}
+
+		let file = std::fs::File::create(path)
+			.with_context(|| format!("Could not create backup file: {}", path.to_string_lossy()))?;
+		let encoder =
+			crate::encoder::Encoder::new(std::io::BufWriter::new(file), password, version)?;
+
+		Ok(Self {
+			encoder: Some(encoder),
+			// we set read frames to 1 due to the header frame written on creation
+			written_frames: 1,
+		})
+	}
+
+	fn encoder(
+		&mut self,
+	) -> Result<&mut crate::encoder::Encoder<std::io::BufWriter<std::fs::File>>, crate::error::Error>
+	{
+		self.encoder
+			.as_mut()
+			.ok_or_else(|| crate::error::Error::Output("backup is already finished".to_string()))
+	}
+}
+
+impl crate::output::SignalOutput for SignalOutputBackup {
+	fn get_written_frames(&self) -> usize {
+		self.written_frames
+	}
+
+	// attached data has to be written together with its frame, so all frames are passed to the
+	// encoder directly
+	fn write_frame(&mut self, frame: crate::frame::Frame) -> Result<(), crate::error::Error> {
+		self.encoder()?.write_frame(frame)?;
+		self.written_frames += 1;
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<(), crate::error::Error> {
+		self.encoder
+			.take()
+			.ok_or_else(|| crate::error::Error::Output("backup is already finished".to_string()))?
+			.finish()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::output::SignalOutput;
+
+	const PASSWORD: &[u8] = b"123451234512345123451234512345";
+	const PASSWORD_NEW: &[u8] = b"543215432154321543215432154321";
+
+	/// Encrypt a version 1 backup with all kinds of frames, including unknown protobuf fields
+	///
+	/// The protobuf messages are encrypted as they are, not via `Frame` and `Encoder`, so fields
+	/// lost by them do not go unnoticed.
+	fn encode() -> Vec<u8> {
+		let mut frames = crate::frame::tests::with_unknown_fields();
+		let header = frames.remove(0);
+		// the end frame of the re-keyed backup is written by the encoder
+		frames.remove(4);
+		// field 42 with varint 1
+		frames.push(protobuf::Message::parse_from_bytes(&[0xd0, 0x02, 0x01]).unwrap());
+		let mut end = crate::Backups::BackupFrame::new();
+		end.set_end(true);
+		frames.push(end);
+
+		let mut backup = Vec::new();
+		let message = protobuf::Message::write_to_bytes(&header).unwrap();
+		backup.extend_from_slice(&(message.len() as u32).to_be_bytes());
+		backup.extend_from_slice(&message);
+		let mut encrypter = crate::encrypter::Encrypter::new(
+			PASSWORD,
+			header.get_header().get_salt(),
+			header.get_header().get_iv(),
+		);
+
+		for frame in frames {
+			let mut message = protobuf::Message::write_to_bytes(&frame).unwrap();
+			let mut length = ((message.len() + crate::decrypter::LENGTH_HMAC) as u32).to_be_bytes();
+			encrypter.encrypt(&mut length);
+			encrypter.encrypt(&mut message);
+			backup.extend_from_slice(&length);
+			backup.extend_from_slice(&message);
+			backup.extend_from_slice(&encrypter.finalize_mac());
+			encrypter.increase_iv();
+
+			if let Some(length) = data_length(&frame) {
+				let mut data = vec![7u8; length];
+				encrypter.mac_update_with_iv();
+				encrypter.encrypt(&mut data);
+				backup.extend_from_slice(&data);
+				backup.extend_from_slice(&encrypter.finalize_mac());
+				encrypter.increase_iv();
+			}
+		}
+		backup
+	}
+
+	/// Length of the attached data of protobuf message `frame`
+	fn data_length(frame: &crate::Backups::BackupFrame) -> Option<usize> {
+		let length = if frame.has_attachment() {
+			frame.get_attachment().get_length()
+		} else if frame.has_avatar() {
+			frame.get_avatar().get_length()
+		} else if frame.has_sticker() {
+			frame.get_sticker().get_length()
+		} else {
+			return None;
+		};
+		Some(length as usize)
+	}
+
+	fn open<'a>(
+		backup: &'a [u8],
+		password: &[u8],
+	) -> Result<crate::input::InputFile<std::io::Cursor<&'a [u8]>>, crate::error::Error> {
+		crate::input::InputFile::new(
+			std::io::Cursor::new(backup),
+			None,
+			&crate::key::Secret::Password(zeroize::Zeroizing::new(password.to_vec())),
+			Default::default(),
+		)
+	}
+
+	/// Return all frames after the header of a version 1 `backup` as decrypted protobuf
+	/// messages, each followed by its attached data
+	///
+	/// The frames are decrypted without parsing them into `Frame`, so nothing lost by `Frame`
+	/// goes unnoticed.
+	fn decrypt(backup: &[u8], password: &[u8]) -> Vec<Vec<u8>> {
+		fn read(reader: &mut std::io::Cursor<&[u8]>, length: usize) -> Vec<u8> {
+			let mut buf = vec![0u8; length];
+			std::io::Read::read_exact(reader, &mut buf).unwrap();
+			buf
+		}
+		fn read_length(
+			reader: &mut std::io::Cursor<&[u8]>,
+			decrypter: Option<&mut crate::decrypter::Decrypter>,
+		) -> usize {
+			let mut length = [0u8; 4];
+			std::io::Read::read_exact(reader, &mut length).unwrap();
+			if let Some(decrypter) = decrypter {
+				decrypter.decrypt(&mut length);
+			}
+			u32::from_be_bytes(length) as usize
+		}
+		let mut reader = std::io::Cursor::new(backup);
+
+		let length = read_length(&mut reader, None);
+		let header: crate::Backups::BackupFrame =
+			protobuf::Message::parse_from_bytes(&read(&mut reader, length)).unwrap();
+		let key = crate::key::BackupKey::derive(password, header.get_header().get_salt());
+		let mut decrypter =
+			crate::decrypter::Decrypter::new(&key, header.get_header().get_iv(), true);
+
+		let mut decrypted = Vec::new();
+		while (reader.position() as usize) < backup.len() {
+			let length = read_length(&mut reader, Some(&mut decrypter));
+			let mut message = read(&mut reader, length - crate::decrypter::LENGTH_HMAC);
+			decrypter.decrypt(&mut message);
+			decrypter
+				.verify_mac(&read(&mut reader, crate::decrypter::LENGTH_HMAC))
+				.unwrap();
+			decrypter.increase_iv();
+
+			let frame: crate::Backups::BackupFrame =
+				protobuf::Message::parse_from_bytes(&message).unwrap();
+			decrypted.push(message);
+			let length = match data_length(&frame) {
+				Some(x) => x,
+				None => continue,
+			};
+
+			decrypter.mac_update_with_iv();
+			let mut data = read(&mut reader, length);
+			decrypter.decrypt(&mut data);
+			decrypter
+				.verify_mac(&read(&mut reader, crate::decrypter::LENGTH_HMAC))
+				.unwrap();
+			decrypter.increase_iv();
+			decrypted.push(data);
+		}
+		decrypted
+	}
+
+	#[test]
+	fn rekey() {
+		let backup = encode();
+		let dir = crate::temp_dir::TempDir::new("rekey");
+		let path = dir.path().join("rekeyed.backup");
+
+		// decode the backup and encode it with a new password
+		let mut input = open(&backup, PASSWORD).unwrap();
+		let mut output = SignalOutputBackup::new(&path, true, PASSWORD_NEW, 1).unwrap();
+		while let Some(frame) = input.next() {
+			let mut frame = frame.unwrap();
+			if frame.get_data_length().is_some() {
+				let mut data = Vec::new();
+				input.read_attachment(&mut data).unwrap();
+				frame.set_data(Box::new(std::io::Cursor::new(data)));
+			}
+			output.write_frame(frame).unwrap();
+		}
+		output.finish().unwrap();
+		let rekeyed = std::fs::read(&path).unwrap();
+
+		let decrypted = decrypt(&backup, PASSWORD);
+		// all frames with attached data and the unknown frame
+		assert_eq!(decrypted.len(), 12);
+		assert_eq!(decrypt(&rekeyed, PASSWORD_NEW), decrypted);
+		assert!(matches!(
+			open(&rekeyed, PASSWORD).unwrap().next(),
+			Some(Err(crate::error::Error::WrongPassword))
+		));
+	}
+}
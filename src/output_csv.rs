@@ -1,6 +1,5 @@
-use anyhow::anyhow;
-use anyhow::Context;
-use log::info;
+use crate::error::Context;
+use log::{info, warn};
 
 /// Write csv output of backup
 pub struct SignalOutputCsv {
@@ -12,15 +11,15 @@ impl SignalOutputCsv {
 	/// Creates new output object
 	///
 	/// `force_write` determines whether existing files will be overwritten.
-	pub fn new(path: &std::path::Path, force_overwrite: bool) -> Result<Self, anyhow::Error> {
+	pub fn new(path: &std::path::Path, force_overwrite: bool) -> Result<Self, crate::error::Error> {
 		info!("Output path: {}", &path.to_string_lossy());
 
 		// check output path
 		if path.exists() && !path.is_dir() {
-			return Err(anyhow!(
+			return Err(crate::error::Error::Config(format!(
 				"{} exists and is not a directory",
 				path.to_string_lossy()
-			));
+			)));
 		} else {
 			std::fs::create_dir_all(&path).with_context(|| {
 				format!("Path could not be created: {}", path.to_string_lossy())
@@ -36,9 +35,7 @@ impl SignalOutputCsv {
 					format!("Could not delete old file: {}", path_csv.to_string_lossy())
 				})?;
 			} else {
-				return Err(anyhow!(
-					"Backup file already exists and may not be overwritten. Try -f"
-				));
+				return Err(crate::error::Error::OutputConflict(path_csv));
 			}
 		}
 
@@ -55,7 +52,7 @@ impl crate::output::SignalOutput for SignalOutputCsv {
 		&mut self,
 		statement: &str,
 		parameters: &[rusqlite::types::Value],
-	) -> Result<(), anyhow::Error> {
+	) -> Result<(), crate::error::Error> {
 		if statement.starts_with("INSERT INTO sms") {
 			let mess = crate::message::Message::new(parameters);
 			self.writer.serialize(mess)?;
@@ -67,20 +64,28 @@ impl crate::output::SignalOutput for SignalOutputCsv {
 
 	fn write_attachment(
 		&mut self,
-		_data: &[u8],
+		_data: &mut dyn std::io::Read,
 		_attachmend_id: u64,
 		_row_id: u64,
-	) -> Result<(), anyhow::Error> {
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn write_sticker(&mut self, _data: &[u8], _row_id: u64) -> Result<(), anyhow::Error> {
+	fn write_sticker(
+		&mut self,
+		_data: &mut dyn std::io::Read,
+		_row_id: u64,
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn write_avatar(&mut self, _data: &[u8], _name: &str) -> Result<(), anyhow::Error> {
+	fn write_avatar(
+		&mut self,
+		_data: &mut dyn std::io::Read,
+		_name: &str,
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
@@ -88,26 +93,36 @@ impl crate::output::SignalOutput for SignalOutputCsv {
 	fn write_preference(
 		&mut self,
 		_pref: &crate::Backups::SharedPreference,
-	) -> Result<(), anyhow::Error> {
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn write_version(&mut self, _version: u32) -> Result<(), anyhow::Error> {
+	fn write_version(&mut self, _version: u32) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn write_key_value(&mut self, key_value: &crate::Backups::KeyValue) ->  Result<(), anyhow::Error>{
+	fn write_key_value(&mut self, key_value: &crate::Backups::KeyValue) ->  Result<(), crate::error::Error>{
 		self.written_frames += 1;
 		Ok(())
 	}
 
+	fn write_unknown(&mut self, _data: &[u8], fields: &[u32]) -> Result<(), crate::error::Error> {
+		warn!("Skipping unknown frame with fields {:?}", fields);
+		self.written_frames += 1;
+		Ok(())
+	}
+
+	fn write_frame(&mut self, frame: crate::frame::Frame) -> Result<(), crate::error::Error> {
+		crate::output::write_frame_by_type(self, frame)
+	}
+
 	fn get_written_frames(&self) -> usize {
 		self.written_frames
 	}
 
-	fn finish(&mut self) -> Result<(), anyhow::Error> {
+	fn finish(&mut self) -> Result<(), crate::error::Error> {
 		Ok(())
 	}
 }
@@ -0,0 +1,551 @@
+use log::{info, warn};
+
+/// Tables containing messages, `sms` and `mms` before database version 168, `message` after
+const TABLES_MESSAGE: [&str; 3] = ["sms", "mms", "message"];
+/// Columns of attachment rows referencing their message
+const COLUMNS_MESSAGE_ID: [&str; 2] = ["mid", "message_id"];
+/// Tables referencing messages and their column containing the message id
+///
+/// Rows of these tables are dropped together with their message. If they have a column
+/// `is_mms`, it tells whether the id belongs to `sms` or `mms`.
+const TABLES_MESSAGE_REFERENCE: [(&str, &str); 5] = [
+	("reaction", "message_id"),
+	("mention", "message_id"),
+	("msl_message", "message_id"),
+	("group_receipts", "mms_id"),
+	("story_sends", "message_id"),
+];
+/// Columns of thread rows containing the snippet of their last message
+const COLUMNS_SNIPPET: [&str; 4] = [
+	"snippet",
+	"snippet_uri",
+	"snippet_content_type",
+	"snippet_extras",
+];
+/// Columns of message rows containing a recipient, `address` is used by old versions
+const COLUMNS_RECIPIENT: [&str; 4] = [
+	"recipient_id",
+	"from_recipient_id",
+	"to_recipient_id",
+	"address",
+];
+/// Columns of message rows containing the date, the first existing one is used
+const COLUMNS_DATE: [&str; 3] = ["date_sent", "date", "date_received"];
+
+/// Criteria of messages to keep
+///
+/// A message is kept if it matches all given criteria. Empty lists and missing dates do not
+/// restrict the messages.
+#[derive(Clone, Default)]
+pub struct Filter {
+	/// Keep messages of these threads
+	pub threads: Vec<i64>,
+	/// Keep messages sent from or to these recipients, either recipient ids or addresses
+	pub recipients: Vec<String>,
+	/// Keep messages sent at or after this time (milliseconds since the epoch)
+	pub since: Option<i64>,
+	/// Keep messages sent before this time (milliseconds since the epoch)
+	pub before: Option<i64>,
+}
+
+impl Filter {
+	/// Does the filter keep all messages?
+	pub fn is_empty(&self) -> bool {
+		self.threads.is_empty()
+			&& self.recipients.is_empty()
+			&& self.since.is_none()
+			&& self.before.is_none()
+	}
+
+	/// Does the filter keep all messages of thread `thread`?
+	fn keeps_thread(&self, thread: i64) -> bool {
+		self.threads.contains(&thread)
+			&& self.recipients.is_empty()
+			&& self.since.is_none()
+			&& self.before.is_none()
+	}
+
+	/// Does `row` of a message table match the filter?
+	fn matches(&self, row: &crate::schema::Row) -> bool {
+		if !self.threads.is_empty() {
+			match row.get_integer("thread_id") {
+				Some(x) if self.threads.contains(&x) => (),
+				_ => return false,
+			}
+		}
+
+		if !self.recipients.is_empty()
+			&& !COLUMNS_RECIPIENT
+				.iter()
+				.filter_map(|x| row.get(x))
+				.any(|x| match x {
+					rusqlite::types::Value::Integer(x) => self.recipients.contains(&x.to_string()),
+					rusqlite::types::Value::Text(x) => self.recipients.contains(x),
+					_ => false,
+				}) {
+			return false;
+		}
+
+		if self.since.is_some() || self.before.is_some() {
+			let date = match row.get_first(&COLUMNS_DATE) {
+				Some(rusqlite::types::Value::Integer(x)) => *x,
+				_ => return false,
+			};
+			if self.since.is_some_and(|x| date < x) || self.before.is_some_and(|x| date >= x) {
+				return false;
+			}
+		}
+
+		true
+	}
+}
+
+/// Write only messages matching a filter to another output
+///
+/// Rows of message tables not matching the filter are dropped together with their attachments
+/// and the rows referencing them, e.g. reactions and mentions. Rows referencing messages are
+/// dropped as well if they are written before their message.
+///
+/// Signal writes the data of each attachment right after its row, so attachments are decided
+/// by their row without buffering the data. Attachments written before their row are dropped
+/// and counted, their data cannot be held back until the row is known.
+///
+/// Thread rows are kept, but the snippet of their last message is removed unless the filter
+/// keeps all messages of the thread. Other tables, e.g. recipients, are kept completely.
+pub struct SignalOutputFilter {
+	output: Box<dyn crate::output::SignalOutput>,
+	filter: Filter,
+	tables: crate::schema::Tables,
+	/// Ids of kept messages of the tables `mms` and `message`, which can have attachments
+	messages: std::collections::HashSet<i64>,
+	/// Ids of kept messages of the table `sms`, they overlap with those of `mms`
+	messages_sms: std::collections::HashSet<i64>,
+	/// Row ids of kept attachments
+	attachments: std::collections::HashSet<u64>,
+	/// Row ids of dropped attachments
+	attachments_dropped: std::collections::HashSet<u64>,
+	/// Number of attachments written before their row
+	count_attachment_without_row: usize,
+	count_kept: usize,
+	count_dropped: usize,
+	dropped_frames: usize,
+}
+
+impl SignalOutputFilter {
+	/// Creates new output object
+	///
+	/// Frames matching `filter` are written to `output`.
+	pub fn new(output: Box<dyn crate::output::SignalOutput>, filter: Filter) -> Self {
+		Self {
+			output,
+			filter,
+			tables: crate::schema::Tables::new(),
+			messages: std::collections::HashSet::new(),
+			messages_sms: std::collections::HashSet::new(),
+			attachments: std::collections::HashSet::new(),
+			attachments_dropped: std::collections::HashSet::new(),
+			count_attachment_without_row: 0,
+			count_kept: 0,
+			count_dropped: 0,
+			dropped_frames: 0,
+		}
+	}
+
+	/// Should `frame` be written?
+	fn keep(&mut self, frame: &crate::frame::Frame) -> bool {
+		match frame {
+			crate::frame::Frame::Statement {
+				statement,
+				parameter,
+				..
+			} => self.keep_statement(statement, parameter),
+			crate::frame::Frame::Attachment { row, .. } => {
+				if self.attachments.contains(row) {
+					return true;
+				}
+				if !self.attachments_dropped.contains(row) {
+					self.count_attachment_without_row += 1;
+				}
+				false
+			}
+			_ => true,
+		}
+	}
+
+	fn keep_statement(&mut self, statement: &str, parameters: &[rusqlite::types::Value]) -> bool {
+		if self.tables.read_statement(statement) {
+			return true;
+		}
+
+		let row = match self.tables.row(statement, parameters) {
+			Some(x) => x,
+			None => {
+				return match crate::schema::parse_insert(statement) {
+					Some(x)
+						if TABLES_MESSAGE.contains(&x)
+							|| crate::schema::TABLES_ATTACHMENT.contains(&x) =>
+					{
+						warn!("Rows of table {} are dropped, its columns are unknown", x);
+						false
+					}
+					_ => true,
+				}
+			}
+		};
+
+		if TABLES_MESSAGE.contains(&row.table) {
+			let keep = self.filter.matches(&row);
+			if keep {
+				self.count_kept += 1;
+				if let Some(x) = row.get_integer("_id") {
+					if row.table == "sms" {
+						self.messages_sms.insert(x);
+					} else {
+						self.messages.insert(x);
+					}
+				}
+			} else {
+				self.count_dropped += 1;
+			}
+			keep
+		} else if crate::schema::TABLES_ATTACHMENT.contains(&row.table) {
+			let keep = match row.get_first(&COLUMNS_MESSAGE_ID) {
+				Some(rusqlite::types::Value::Integer(x)) => self.messages.contains(x),
+				_ => false,
+			};
+			if let Some(x) = row.get_integer("_id") {
+				if keep {
+					self.attachments.insert(x as u64);
+				} else {
+					self.attachments_dropped.insert(x as u64);
+				}
+			}
+			keep
+		} else if let Some((_, column)) = TABLES_MESSAGE_REFERENCE
+			.iter()
+			.find(|(table, _)| *table == row.table)
+		{
+			let messages = match row.get_integer("is_mms") {
+				Some(0) => &self.messages_sms,
+				_ => &self.messages,
+			};
+			match row.get_integer(column) {
+				Some(x) => messages.contains(&x),
+				None => false,
+			}
+		} else {
+			true
+		}
+	}
+
+	/// Remove the snippet from `frame` if it is a row of a thread not kept completely
+	fn remove_snippet(&self, frame: &mut crate::frame::Frame) {
+		if let crate::frame::Frame::Statement {
+			statement,
+			parameter,
+			..
+		} = frame
+		{
+			let columns: Vec<usize> = match self.tables.row(statement, parameter) {
+				Some(row) if row.table == "thread" => match row.get_integer("_id") {
+					Some(x) if self.filter.keeps_thread(x) => return,
+					_ => COLUMNS_SNIPPET
+						.iter()
+						.filter_map(|x| row.position(x))
+						.collect(),
+				},
+				_ => return,
+			};
+			for i in columns {
+				parameter[i] = rusqlite::types::Value::Null;
+			}
+		}
+	}
+}
+
+impl crate::output::SignalOutput for SignalOutputFilter {
+	// dropped frames count as written to keep up with the read frames
+	fn get_written_frames(&self) -> usize {
+		self.output.get_written_frames() + self.dropped_frames
+	}
+
+	fn write_frame(&mut self, mut frame: crate::frame::Frame) -> Result<(), crate::error::Error> {
+		if self.keep(&frame) {
+			self.remove_snippet(&mut frame);
+			self.output.write_frame(frame)
+		} else {
+			self.dropped_frames += 1;
+			Ok(())
+		}
+	}
+
+	fn finish(&mut self) -> Result<(), crate::error::Error> {
+		info!(
+			"Filter: {} messages kept, {} messages dropped",
+			self.count_kept, self.count_dropped
+		);
+		if self.count_attachment_without_row > 0 {
+			warn!(
+				"Filter: {} attachments written before their row dropped",
+				self.count_attachment_without_row
+			);
+		}
+		self.output.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::output::SignalOutput;
+	use rusqlite::types::Value;
+
+	fn statement(statement: &str, parameter: Vec<Value>) -> crate::frame::Frame {
+		crate::frame::Frame::Statement {
+			statement: statement.to_string(),
+			parameter,
+			unknown_fields: Default::default(),
+		}
+	}
+
+	fn attachment(row: u64) -> crate::frame::Frame {
+		crate::frame::Frame::Attachment {
+			data_length: 0,
+			id: 1,
+			row,
+			data: None,
+			unknown_fields: Default::default(),
+		}
+	}
+
+	#[test]
+	fn filter_messages() {
+		let mut output = SignalOutputFilter::new(
+			Box::new(crate::output_none::SignalOutputNone::new()),
+			Filter {
+				threads: vec![2],
+				since: Some(1000),
+				before: Some(2000),
+				..Default::default()
+			},
+		);
+
+		let frames = vec![
+			(
+				statement(
+					"CREATE TABLE mms (_id INTEGER PRIMARY KEY, thread_id INTEGER, date_sent INTEGER)",
+					vec![],
+				),
+				true,
+			),
+			(
+				statement(
+					"CREATE TABLE part (_id INTEGER PRIMARY KEY, mid INTEGER)",
+					vec![],
+				),
+				true,
+			),
+			// wrong thread
+			(
+				statement(
+					"INSERT INTO mms VALUES (?,?,?)",
+					vec![Value::Integer(1), Value::Integer(1), Value::Integer(1500)],
+				),
+				false,
+			),
+			// too late
+			(
+				statement(
+					"INSERT INTO mms VALUES (?,?,?)",
+					vec![Value::Integer(2), Value::Integer(2), Value::Integer(2000)],
+				),
+				false,
+			),
+			(
+				statement(
+					"INSERT INTO mms VALUES (?,?,?)",
+					vec![Value::Integer(3), Value::Integer(2), Value::Integer(1000)],
+				),
+				true,
+			),
+			(
+				statement(
+					"INSERT INTO part VALUES (?,?)",
+					vec![Value::Integer(10), Value::Integer(1)],
+				),
+				false,
+			),
+			(attachment(10), false),
+			(
+				statement(
+					"INSERT INTO part VALUES (?,?)",
+					vec![Value::Integer(11), Value::Integer(3)],
+				),
+				true,
+			),
+			(attachment(11), true),
+			// attachments before their row cannot be decided and are dropped
+			(attachment(12), false),
+			(
+				statement(
+					"INSERT INTO part VALUES (?,?)",
+					vec![Value::Integer(12), Value::Integer(3)],
+				),
+				true,
+			),
+			(
+				statement("INSERT INTO thread VALUES (?)", vec![Value::Integer(1)]),
+				true,
+			),
+			(
+				statement(
+					"CREATE TABLE reaction (_id INTEGER PRIMARY KEY, message_id INTEGER, is_mms INTEGER)",
+					vec![],
+				),
+				true,
+			),
+			(
+				statement(
+					"CREATE TABLE mention (_id INTEGER PRIMARY KEY, thread_id INTEGER, message_id INTEGER)",
+					vec![],
+				),
+				true,
+			),
+			// reaction to a dropped message
+			(
+				statement(
+					"INSERT INTO reaction VALUES (?,?,?)",
+					vec![Value::Integer(1), Value::Integer(1), Value::Integer(1)],
+				),
+				false,
+			),
+			(
+				statement(
+					"INSERT INTO reaction VALUES (?,?,?)",
+					vec![Value::Integer(2), Value::Integer(3), Value::Integer(1)],
+				),
+				true,
+			),
+			// id of an unknown sms
+			(
+				statement(
+					"INSERT INTO reaction VALUES (?,?,?)",
+					vec![Value::Integer(3), Value::Integer(3), Value::Integer(0)],
+				),
+				false,
+			),
+			(
+				statement(
+					"INSERT INTO mention VALUES (?,?,?)",
+					vec![Value::Integer(1), Value::Integer(2), Value::Integer(2)],
+				),
+				false,
+			),
+			(
+				statement(
+					"INSERT INTO mention VALUES (?,?,?)",
+					vec![Value::Integer(2), Value::Integer(2), Value::Integer(3)],
+				),
+				true,
+			),
+		];
+
+		for (frame, keep) in frames {
+			assert_eq!(output.keep(&frame), keep, "{}", frame);
+		}
+		// only the attachment before its row is counted, not the one of a dropped row
+		assert_eq!(output.count_attachment_without_row, 1);
+	}
+
+	#[test]
+	fn remove_snippet() {
+		let thread = |id: i64| {
+			statement(
+				"INSERT INTO thread VALUES (?,?,?)",
+				vec![
+					Value::Integer(id),
+					Value::Text("hello".to_string()),
+					Value::Integer(1),
+				],
+			)
+		};
+		let snippet = |filter: Filter, id: i64| {
+			let mut output = SignalOutputFilter::new(
+				Box::new(crate::output_none::SignalOutputNone::new()),
+				filter,
+			);
+			assert!(output.keep(&statement(
+				"CREATE TABLE thread (_id INTEGER PRIMARY KEY, snippet TEXT, snippet_type INTEGER)",
+				vec![],
+			)));
+			let mut frame = thread(id);
+			output.remove_snippet(&mut frame);
+			match frame {
+				crate::frame::Frame::Statement { parameter, .. } => {
+					assert_eq!(parameter[2], Value::Integer(1));
+					parameter[1].clone()
+				}
+				_ => unreachable!(),
+			}
+		};
+
+		let threads = Filter {
+			threads: vec![1],
+			..Default::default()
+		};
+		assert_eq!(
+			snippet(threads.clone(), 1),
+			Value::Text("hello".to_string())
+		);
+		assert_eq!(snippet(threads.clone(), 2), Value::Null);
+		assert_eq!(
+			snippet(
+				Filter {
+					since: Some(1000),
+					..threads
+				},
+				1
+			),
+			Value::Null
+		);
+	}
+
+	#[test]
+	fn filter_recipients() {
+		let filter = Filter {
+			recipients: vec!["5".to_string(), "+49123".to_string()],
+			..Default::default()
+		};
+		let mut tables = crate::schema::Tables::new();
+		tables.read_statement("CREATE TABLE sms (_id INTEGER PRIMARY KEY, address TEXT)");
+		let matches = |values: Vec<Value>| {
+			filter.matches(&tables.row("INSERT INTO sms VALUES (?,?)", &values).unwrap())
+		};
+
+		assert!(matches(vec![Value::Integer(1), Value::Integer(5)]));
+		assert!(matches(vec![
+			Value::Integer(1),
+			Value::Text("+49123".to_string())
+		]));
+		assert!(!matches(vec![Value::Integer(1), Value::Integer(6)]));
+	}
+
+	#[test]
+	fn written_frames() {
+		let mut output = SignalOutputFilter::new(
+			Box::new(crate::output_none::SignalOutputNone::new()),
+			Filter {
+				threads: vec![1],
+				..Default::default()
+			},
+		);
+		output.write_frame(attachment(1)).unwrap();
+		output
+			.write_frame(crate::frame::Frame::Version {
+				version: 1,
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		assert_eq!(output.get_written_frames(), 3);
+	}
+}
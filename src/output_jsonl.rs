@@ -0,0 +1,62 @@
+use crate::error::Context;
+use log::info;
+use std::io::Write;
+
+/// Write frames as JSON lines
+///
+/// Every frame is serialized to one line, see `crate::frame::Frame` for the format. Attached
+/// data of attachments, avatars and stickers is not written.
+pub struct SignalOutputJsonl {
+	writer: std::io::BufWriter<Box<dyn Write + Send>>,
+	written_frames: usize,
+}
+
+impl SignalOutputJsonl {
+	/// Creates new output object
+	///
+	/// The frames are written to file `path`, `-` writes to stdout. `force_write` determines
+	/// whether an existing file will be overwritten.
+	pub fn new(path: &std::path::Path, force_write: bool) -> Result<Self, crate::error::Error> {
+		let writer: Box<dyn Write + Send> = if path == std::path::Path::new("-") {
+			info!("Output file: stdout");
+			Box::new(std::io::stdout())
+		} else {
+			info!("Output file: {}", &path.to_string_lossy());
+
+			if path.exists() && !force_write {
+				return Err(crate::error::Error::OutputConflict(path.to_path_buf()));
+			}
+
+			Box::new(std::fs::File::create(path).with_context(|| {
+				format!("Could not create output file: {}", path.to_string_lossy())
+			})?)
+		};
+
+		Ok(Self {
+			writer: std::io::BufWriter::new(writer),
+			// we set read frames to 1 due to the header frame we will never write
+			written_frames: 1,
+		})
+	}
+}
+
+impl crate::output::SignalOutput for SignalOutputJsonl {
+	fn get_written_frames(&self) -> usize {
+		self.written_frames
+	}
+
+	// all frames are serialized the same way, the attached data is dropped unread
+	fn write_frame(&mut self, frame: crate::frame::Frame) -> Result<(), crate::error::Error> {
+		serde_json::to_writer(&mut self.writer, &frame)
+			.map_err(std::io::Error::from)
+			.and_then(|_| self.writer.write_all(b"\n"))
+			.with_context(|| format!("Could not write frame: {}", frame))?;
+		self.written_frames += 1;
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<(), crate::error::Error> {
+		self.writer.flush().context("Could not flush output")?;
+		Ok(())
+	}
+}
@@ -22,32 +22,46 @@ impl SignalOutputNone {
 	}
 }
 
+impl Default for SignalOutputNone {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl crate::output::SignalOutput for SignalOutputNone {
 	fn write_statement(
 		&mut self,
 		_statement: &str,
 		_parameters: &[rusqlite::types::Value],
-	) -> Result<(), anyhow::Error> {
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
 	fn write_attachment(
 		&mut self,
-		_data: &[u8],
+		_data: &mut dyn std::io::Read,
 		_attachmend_id: u64,
 		_row_id: u64,
-	) -> Result<(), anyhow::Error> {
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn write_sticker(&mut self, _data: &[u8], _row_id: u64) -> Result<(), anyhow::Error> {
+	fn write_sticker(
+		&mut self,
+		_data: &mut dyn std::io::Read,
+		_row_id: u64,
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn write_avatar(&mut self, _data: &[u8], _name: &str) -> Result<(), anyhow::Error> {
+	fn write_avatar(
+		&mut self,
+		_data: &mut dyn std::io::Read,
+		_name: &str,
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
@@ -55,26 +69,35 @@ impl crate::output::SignalOutput for SignalOutputNone {
 	fn write_preference(
 		&mut self,
 		_pref: &crate::Backups::SharedPreference,
-	) -> Result<(), anyhow::Error> {
+	) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn write_version(&mut self, _version: u32) -> Result<(), anyhow::Error> {
+	fn write_version(&mut self, _version: u32) -> Result<(), crate::error::Error> {
 		self.written_frames += 1;
 		Ok(())
 	}
 
+	fn write_unknown(&mut self, _data: &[u8], _fields: &[u32]) -> Result<(), crate::error::Error> {
+		self.written_frames += 1;
+		Ok(())
+	}
+
+	fn write_frame(&mut self, frame: crate::frame::Frame) -> Result<(), crate::error::Error> {
+		crate::output::write_frame_by_type(self, frame)
+	}
+
 	fn get_written_frames(&self) -> usize {
 		self.written_frames
 	}
 
-	fn write_key_value(&mut self, key_value: &crate::Backups::KeyValue) ->  Result<(), anyhow::Error>{
+	fn write_key_value(&mut self, key_value: &crate::Backups::KeyValue) ->  Result<(), crate::error::Error>{
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn finish(&mut self) -> Result<(), anyhow::Error> {
+	fn finish(&mut self) -> Result<(), crate::error::Error> {
 		Ok(())
 	}
 }
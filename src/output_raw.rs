@@ -1,12 +1,140 @@
-use anyhow::anyhow;
-use anyhow::Context;
-use log::{debug, info};
+use crate::error::Context;
+use log::{debug, info, warn};
+use std::io::Read;
 use std::io::Write;
 
+/// Number of bytes used to guess the file type of attachments
+const LENGTH_INFER: usize = 8192;
+/// Maximum length of file names taken from attachments in bytes, excluding the extension
+const LENGTH_FILE_NAME: usize = 200;
+/// File listing the paths of all attachments
+const FILE_ATTACHMENTS: &str = "attachments.csv";
+/// File listing all key value pairs, only written if requested
+const FILE_KEY_VALUE: &str = "key_value.json";
+
+/// Extensions of common content types of attachments
+const EXTENSIONS: [(&str, &str); 36] = [
+	("application/msword", "doc"),
+	("application/pdf", "pdf"),
+	("application/vnd.android.package-archive", "apk"),
+	("application/vnd.ms-excel", "xls"),
+	("application/vnd.ms-powerpoint", "ppt"),
+	("application/vnd.oasis.opendocument.text", "odt"),
+	(
+		"application/vnd.openxmlformats-officedocument.presentationml.presentation",
+		"pptx",
+	),
+	(
+		"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+		"xlsx",
+	),
+	(
+		"application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+		"docx",
+	),
+	("application/zip", "zip"),
+	("audio/aac", "aac"),
+	("audio/amr", "amr"),
+	("audio/flac", "flac"),
+	("audio/mp4", "m4a"),
+	("audio/mpeg", "mp3"),
+	("audio/ogg", "ogg"),
+	("audio/opus", "opus"),
+	("audio/wav", "wav"),
+	("audio/x-wav", "wav"),
+	("image/bmp", "bmp"),
+	("image/gif", "gif"),
+	("image/heic", "heic"),
+	("image/heif", "heif"),
+	("image/jpeg", "jpg"),
+	("image/png", "png"),
+	("image/svg+xml", "svg"),
+	("image/webp", "webp"),
+	("text/html", "html"),
+	("text/plain", "txt"),
+	("text/vcard", "vcf"),
+	("text/x-signal-plain", "txt"),
+	("text/x-vcard", "vcf"),
+	("video/3gpp", "3gp"),
+	("video/mp4", "mp4"),
+	("video/quicktime", "mov"),
+	("video/webm", "webm"),
+];
+
+/// Format of the files preferences are written to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PreferenceFormat {
+	/// XML like the shared preferences of Android
+	Xml,
+	/// INI, booleans are written as `true` or `false` and string sets as JSON arrays
+	Ini,
+	/// JSON object with strings, booleans and arrays of strings
+	Json,
+}
+
+impl PreferenceFormat {
+	/// Extension of preference files
+	fn extension(self) -> Option<&'static str> {
+		match self {
+			Self::Xml => Some("xml"),
+			// kept without extension like older versions
+			Self::Ini => None,
+			Self::Json => Some("json"),
+		}
+	}
+}
+
+/// Value of a preference
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(untagged)]
+enum Preference {
+	String(String),
+	Boolean(bool),
+	StringSet(Vec<String>),
+}
+
+impl Preference {
+	fn new(pref: &crate::Backups::SharedPreference) -> Self {
+		if pref.get_isStringSetValue() {
+			Self::StringSet(pref.get_stringSetValue().to_vec())
+		} else if pref.has_booleanValue() {
+			Self::Boolean(pref.get_booleanValue())
+		} else {
+			Self::String(pref.get_value().to_string())
+		}
+	}
+}
+
+/// Preferences of one file by key
+type Preferences = std::collections::BTreeMap<String, Preference>;
+
+/// File written by `SignalOutputRaw::write_to_file`
+struct WrittenFile {
+	path: std::path::PathBuf,
+	/// Content type guessed from the data
+	mime: Option<&'static str>,
+	/// File in the store the file is linked to
+	object: Option<crate::store::Object>,
+}
+
+/// Attachment file written to the output directory
+struct Attachment {
+	id: u64,
+	path: std::path::PathBuf,
+	/// Was the row of the attachment read yet?
+	named: bool,
+}
+
 /// Write raw backup
 ///
 /// This output module writes the backup in a sqlite database and media files in different
 /// directories.
+///
+/// If a store is given, the media files are written to the store and linked to their location
+/// in the output directory. The hash, size and content type of all attachments are recorded in
+/// the table `attachment_store` of the database.
+///
+/// Key value pairs are written to the table `key_value` with one column per type of value.
 pub struct SignalOutputRaw {
 	path_output: std::path::PathBuf,
 	force_write: bool,
@@ -15,27 +143,45 @@ pub struct SignalOutputRaw {
 	count_attachment: usize,
 	count_sticker: usize,
 	count_avatar: usize,
+	count_unknown: usize,
 	written_frames: usize,
-	created_files: std::boxed::Box<std::collections::HashSet<std::path::PathBuf>>
+	created_files: std::boxed::Box<std::collections::HashSet<std::path::PathBuf>>,
+	tables: crate::schema::Tables,
+	/// File name and content type of attachment rows read before their attachment
+	attachment_names: std::collections::HashMap<u64, (Option<String>, Option<String>)>,
+	/// Attachments written by row id
+	attachments: std::collections::BTreeMap<u64, Attachment>,
+	store: Option<crate::store::Store>,
+	/// Key value pairs written so far, only kept if they are written to a JSON file
+	key_values: Option<Vec<crate::Backups::KeyValue>>,
+	preference_format: PreferenceFormat,
+	/// Preferences by file, they are written at the end
+	preferences: std::collections::BTreeMap<std::path::PathBuf, Preferences>,
 }
 
 impl SignalOutputRaw {
 	/// Creates new output object
 	///
-	/// `force_write` determines whether existing files will be overwritten.
+	/// `force_write` determines whether existing files will be overwritten. Media files are
+	/// written to the store at `path_store` if it is given. If `key_value_json` is set, key
+	/// value pairs are also written to a JSON file. Preferences are written in
+	/// `preference_format`.
 	pub fn new(
 		path: &std::path::Path,
 		force_write: bool,
 		open_db_in_memory: bool,
-	) -> Result<Self, anyhow::Error> {
+		path_store: Option<&std::path::Path>,
+		key_value_json: bool,
+		preference_format: PreferenceFormat,
+	) -> Result<Self, crate::error::Error> {
 		info!("Output path: {}", &path.to_string_lossy());
 
 		// check output path
 		if path.exists() && !path.is_dir() {
-			return Err(anyhow!(
+			return Err(crate::error::Error::Config(format!(
 				"{} exists and is not a directory",
 				path.to_string_lossy()
-			));
+			)));
 		} else {
 			std::fs::create_dir_all(&path).with_context(|| {
 				format!("Path could not be created: {}", path.to_string_lossy())
@@ -54,13 +200,20 @@ impl SignalOutputRaw {
 					)
 				})?;
 			} else {
-				return Err(anyhow!(
-					"Backup database already exists: {}. Try -f",
-					path_sqlite.to_string_lossy()
-				));
+				return Err(crate::error::Error::OutputConflict(path_sqlite));
 			}
 		}
 
+		// the list of attachments is written at the end
+		let path_attachments = path.join(FILE_ATTACHMENTS);
+		if path_attachments.exists() && !force_write {
+			return Err(crate::error::Error::OutputConflict(path_attachments));
+		}
+		let path_key_value = path.join(FILE_KEY_VALUE);
+		if key_value_json && path_key_value.exists() && !force_write {
+			return Err(crate::error::Error::OutputConflict(path_key_value));
+		}
+
 		let sqlite_connection = if open_db_in_memory {
 			rusqlite::Connection::open_in_memory()
 				.with_context(|| "could not open connection to in memory database".to_string())?
@@ -73,6 +226,29 @@ impl SignalOutputRaw {
 			})?
 		};
 
+		sqlite_connection
+			.execute(
+				"CREATE TABLE key_value (key TEXT NOT NULL, blob_value BLOB, boolean_value INTEGER, \
+				 float_value REAL, integer_value INTEGER, long_value INTEGER, string_value TEXT)",
+				rusqlite::NO_PARAMS,
+			)
+			.context("Failed to create table of key value pairs")?;
+
+		let store = match path_store {
+			Some(x) => {
+				info!("Store path: {}", &x.to_string_lossy());
+				sqlite_connection
+					.execute(
+						"CREATE TABLE attachment_store (attachment_id INTEGER, row_id INTEGER, \
+						 hash TEXT NOT NULL, size INTEGER NOT NULL, mime TEXT)",
+						rusqlite::NO_PARAMS,
+					)
+					.context("Failed to create table of stored attachments")?;
+				Some(crate::store::Store::open(x)?)
+			}
+			None => None,
+		};
+
 		Ok(Self {
 			path_output: path.to_path_buf(),
 			force_write,
@@ -81,56 +257,377 @@ impl SignalOutputRaw {
 			count_attachment: 0,
 			count_sticker: 0,
 			count_avatar: 0,
+			count_unknown: 0,
 			// we set read frames to 1 due to the header frame we will never write
 			written_frames: 1,
-			created_files: std::boxed::Box::new(std::collections::HashSet::new())
+			created_files: std::boxed::Box::new(std::collections::HashSet::new()),
+			tables: crate::schema::Tables::new(),
+			attachment_names: std::collections::HashMap::new(),
+			attachments: std::collections::BTreeMap::new(),
+			store,
+			key_values: if key_value_json {
+				Some(Vec::new())
+			} else {
+				None
+			},
+			preference_format,
+			preferences: std::collections::BTreeMap::new(),
 		})
 	}
 
+	/// Write `data` to file `filename` in directory `path_specific`
+	///
+	/// If `infer_extension` is set, the extension is guessed from the data. If a file of the same
+	/// name was written before, a number is added to the name.
 	fn write_to_file(
-		&self,
+		&mut self,
 		path_specific: &str,
 		filename: &str,
-		data: &[u8],
-	) -> Result<(), anyhow::Error> {
+		data: &mut dyn std::io::Read,
+		infer_extension: bool,
+	) -> Result<WrittenFile, crate::error::Error> {
 		// create path to attachment file
 		let path = self.path_output.join(path_specific);
 		std::fs::create_dir_all(&path)
 			.with_context(|| format!("Failed to create path: {}", path.to_string_lossy()))?;
 
+		// read start of data to guess file type
+		let mut head = Vec::with_capacity(LENGTH_INFER);
+		data.take(LENGTH_INFER as u64)
+			.read_to_end(&mut head)
+			.context("Failed to read data")?;
+
 		// add filename and extension to path
 		let mut path = path.join(filename);
-		let infer = infer::Infer::new();
-		if let Some(x) = infer.get(&data) {
-			path.set_extension(x.extension());
+		let file_type = infer::Infer::new().get(&head);
+		if infer_extension {
+			if let Some(ref x) = file_type {
+				path.set_extension(x.extension());
+			}
 		}
 
-		if path.exists() && !self.force_write {
-			return Err(anyhow!(
-				"File does already exist: {}. Try -f",
-				path.to_string_lossy()
-			));
+		let path = self.unique_path(path);
+		if path.exists() {
+			if !self.force_write {
+				return Err(crate::error::Error::OutputConflict(path));
+			}
+			// the old file may be linked to a store, so it must not be changed
+			std::fs::remove_file(&path).with_context(|| {
+				format!("Could not delete old file: {}", path.to_string_lossy())
+			})?;
+		}
+
+		let object = match self.store {
+			Some(ref mut store) => {
+				let object = store.write(&head, data)?;
+				crate::store::link(&object, &path)?;
+				Some(object)
+			}
+			None => {
+				// open connection to file
+				let mut buffer = std::fs::File::create(&path)
+					.with_context(|| format!("Failed to open file: {}", path.to_string_lossy()))?;
+
+				// write to file, a partly written file is deleted
+				if let Err(e) = buffer
+					.write_all(&head)
+					.and_then(|_| std::io::copy(data, &mut buffer))
+				{
+					let _ = std::fs::remove_file(&path);
+					return Err(e).with_context(|| {
+						format!("Failed to write to file: {}", path.to_string_lossy())
+					});
+				}
+				None
+			}
+		};
+
+		self.created_files.insert(path.clone());
+		Ok(WrittenFile {
+			path,
+			mime: file_type.map(|x| x.mime_type()),
+			object,
+		})
+	}
+
+	/// Add a number to the name of `path` if a file of this name was written before
+	fn unique_path(&self, path: std::path::PathBuf) -> std::path::PathBuf {
+		let stem = path
+			.file_stem()
+			.unwrap_or_default()
+			.to_string_lossy()
+			.into_owned();
+		let extension = path
+			.extension()
+			.map(|x| format!(".{}", x.to_string_lossy()))
+			.unwrap_or_default();
+
+		let mut unique = path.clone();
+		let mut count = 1;
+		while self.created_files.contains(&unique) {
+			unique.set_file_name(format!("{} ({}){}", stem, count, extension));
+			count += 1;
+		}
+		unique
+	}
+
+	/// Name the attachment in row `row` of an attachment table
+	///
+	/// If the attachment has already been written, it is renamed.
+	fn name_attachment(
+		&mut self,
+		row: u64,
+		file_name: Option<String>,
+		content_type: Option<String>,
+	) -> Result<(), crate::error::Error> {
+		let (id, path_old) = match self.attachments.get(&row) {
+			Some(x) if x.named => return Ok(()),
+			Some(x) => (x.id, x.path.clone()),
+			None => {
+				self.attachment_names.insert(row, (file_name, content_type));
+				return Ok(());
+			}
+		};
+
+		// the content type of the row is more reliable than the guessed one
+		if let (Some(_), Some(ref x)) = (&self.store, &content_type) {
+			self.sqlite_connection
+				.execute(
+					"UPDATE attachment_store SET mime = ? WHERE row_id = ?",
+					&[x as &dyn rusqlite::ToSql, &(row as i64)],
+				)
+				.context("Failed to update table of stored attachments")?;
+		}
+
+		if let Some(name) =
+			attachment_file_name(file_name.as_deref(), content_type.as_deref(), id, row)
+		{
+			// the file may keep its own name
+			self.created_files.remove(&path_old);
+			let path = self.unique_path(path_old.with_file_name(name));
+			if path != path_old {
+				if path.exists() && !self.force_write {
+					return Err(crate::error::Error::OutputConflict(path));
+				}
+				std::fs::rename(&path_old, &path).with_context(|| {
+					format!(
+						"Failed to rename file {} to {}",
+						path_old.to_string_lossy(),
+						path.to_string_lossy()
+					)
+				})?;
+			}
+			self.created_files.insert(path.clone());
+			self.attachments.get_mut(&row).unwrap().path = path;
 		}
+		self.attachments.get_mut(&row).unwrap().named = true;
+
+		Ok(())
+	}
 
-		// open connection to file
-		let mut buffer = std::fs::File::create(&path)
+	/// Write the id, row id and path of all attachments
+	fn write_attachment_list(&self) -> Result<(), crate::error::Error> {
+		let path = self.path_output.join(FILE_ATTACHMENTS);
+		let mut writer = csv::Writer::from_path(&path)
 			.with_context(|| format!("Failed to open file: {}", path.to_string_lossy()))?;
+		writer.write_record(["attachment_id", "row_id", "path"])?;
+		for (row, attachment) in self.attachments.iter() {
+			let path = attachment
+				.path
+				.strip_prefix(&self.path_output)
+				.unwrap_or(&attachment.path);
+			writer.write_record(&[
+				attachment.id.to_string(),
+				row.to_string(),
+				path.to_string_lossy().into_owned(),
+			])?;
+		}
+		writer
+			.flush()
+			.with_context(|| format!("Failed to write to file: {}", path.to_string_lossy()))?;
+
+		Ok(())
+	}
+
+	/// Write all preferences, one file per preference file of the backup
+	fn write_preferences(&self) -> Result<(), crate::error::Error> {
+		for (path, preferences) in self.preferences.iter() {
+			if let Some(x) = path.parent() {
+				std::fs::create_dir_all(x)
+					.with_context(|| format!("Failed to create path: {}", x.to_string_lossy()))?;
+			}
 
-		// write to file
-		buffer
-			.write_all(data)
+			let data = match self.preference_format {
+				PreferenceFormat::Xml => preferences_xml(preferences),
+				PreferenceFormat::Ini => preferences_ini(preferences),
+				PreferenceFormat::Json => serde_json::to_string_pretty(preferences)
+					.map_err(|e| crate::error::Error::Output(e.to_string()))?,
+			};
+			std::fs::write(path, data).with_context(|| {
+				format!(
+					"Could not write to preference file: {}",
+					path.to_string_lossy()
+				)
+			})?;
+		}
+
+		Ok(())
+	}
+
+	/// Write all key value pairs to a JSON file
+	fn write_key_value_list(
+		&self,
+		key_values: &[crate::Backups::KeyValue],
+	) -> Result<(), crate::error::Error> {
+		let path = self.path_output.join(FILE_KEY_VALUE);
+		let file = std::fs::File::create(&path)
+			.with_context(|| format!("Failed to open file: {}", path.to_string_lossy()))?;
+		let mut writer = std::io::BufWriter::new(file);
+		serde_json::to_writer_pretty(&mut writer, key_values)
+			.map_err(std::io::Error::from)
+			.and_then(|_| writer.flush())
 			.with_context(|| format!("Failed to write to file: {}", path.to_string_lossy()))?;
 
 		Ok(())
 	}
 }
 
+/// Preferences as XML file like the shared preferences of Android
+fn preferences_xml(preferences: &Preferences) -> String {
+	let mut xml = String::from("<?xml version='1.0' encoding='utf-8' standalone='yes' ?>\n<map>\n");
+	for (key, value) in preferences.iter() {
+		let key = escape_xml(key);
+		match value {
+			Preference::String(x) => xml.push_str(&format!(
+				"    <string name=\"{}\">{}</string>\n",
+				key,
+				escape_xml(x)
+			)),
+			Preference::Boolean(x) => xml.push_str(&format!(
+				"    <boolean name=\"{}\" value=\"{}\" />\n",
+				key, x
+			)),
+			Preference::StringSet(x) => {
+				xml.push_str(&format!("    <set name=\"{}\">\n", key));
+				for x in x.iter() {
+					xml.push_str(&format!("        <string>{}</string>\n", escape_xml(x)));
+				}
+				xml.push_str("    </set>\n");
+			}
+		}
+	}
+	xml.push_str("</map>\n");
+	xml
+}
+
+/// Preferences as INI file without sections
+fn preferences_ini(preferences: &Preferences) -> String {
+	let mut conf = ini::Ini::new();
+	for (key, value) in preferences.iter() {
+		let value = match value {
+			Preference::String(x) => x.clone(),
+			Preference::Boolean(x) => x.to_string(),
+			Preference::StringSet(x) => serde_json::to_string(x).unwrap(),
+		};
+		conf.with_section(None::<String>).set(key.as_str(), value);
+	}
+
+	let mut ini = Vec::new();
+	// writing to a vector does not fail
+	conf.write_to(&mut ini).unwrap();
+	String::from_utf8(ini).unwrap()
+}
+
+/// Escape the characters with special meaning in XML
+fn escape_xml(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			_ => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// File name of an attachment from its original `file_name` and `content_type`
+///
+/// The extension of the content type is added to file names without extension. Attachments
+/// without file name are named by `id` and `row`. Returns `None` if neither the file name nor
+/// the extension is known.
+fn attachment_file_name(
+	file_name: Option<&str>,
+	content_type: Option<&str>,
+	id: u64,
+	row: u64,
+) -> Option<String> {
+	let extension = content_type.and_then(|x| {
+		let mime = x.split(';').next().unwrap().trim().to_lowercase();
+		EXTENSIONS
+			.iter()
+			.find(|(content_type, _)| *content_type == mime)
+			.map(|(_, extension)| *extension)
+	});
+
+	match (file_name.and_then(sanitize_file_name), extension) {
+		(Some(name), Some(extension)) if std::path::Path::new(&name).extension().is_none() => {
+			Some(format!("{}.{}", name, extension))
+		}
+		(Some(name), _) => Some(name),
+		(None, Some(extension)) => Some(format!("{}_{}.{}", id, row, extension)),
+		(None, None) => None,
+	}
+}
+
+/// Make the file name `name` of an attachment safe to use
+///
+/// Directories and characters not allowed on common file systems are removed, long names are
+/// shortened.
+fn sanitize_file_name(name: &str) -> Option<String> {
+	let name = name.rsplit(['/', '\\']).next().unwrap();
+	let name: String = name
+		.chars()
+		.map(|c| {
+			if c.is_control() || ":*?\"<>|".contains(c) {
+				'_'
+			} else {
+				c
+			}
+		})
+		.collect();
+	// leading dots would hide the file or refer to a directory
+	let name = name.trim().trim_start_matches('.');
+	if name.is_empty() {
+		return None;
+	}
+
+	let path = std::path::Path::new(name);
+	let extension = path
+		.extension()
+		.map(|x| format!(".{}", x.to_string_lossy()))
+		.unwrap_or_default();
+	let mut stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+	if stem.len() > LENGTH_FILE_NAME {
+		let mut end = LENGTH_FILE_NAME;
+		while !stem.is_char_boundary(end) {
+			end -= 1;
+		}
+		stem.truncate(end);
+	}
+
+	Some(format!("{}{}", stem, extension))
+}
+
 impl crate::output::SignalOutput for SignalOutputRaw {
 	fn write_statement(
 		&mut self,
 		statement: &str,
 		parameters: &[rusqlite::types::Value],
-	) -> Result<(), anyhow::Error> {
+	) -> Result<(), crate::error::Error> {
 		// In database version 9 signal added full text search and uses TRIGGERs to create the virtual tables. however this breaks when importing the data.
 		if statement.starts_with("CREATE TRIGGER")
 			|| statement.contains("_fts")
@@ -140,6 +637,19 @@ impl crate::output::SignalOutput for SignalOutputRaw {
 			return Ok(());
 		}
 
+		// attachments are named after the file name in their row
+		if !self.tables.read_statement(statement) {
+			if let Some(row) = self.tables.row(statement, parameters) {
+				if crate::schema::TABLES_ATTACHMENT.contains(&row.table) {
+					if let Some(id) = row.get_integer("_id") {
+						let file_name = row.get_text(&["file_name"]).map(String::from);
+						let content_type = row.get_text(&["content_type", "ct"]).map(String::from);
+						self.name_attachment(id as u64, file_name, content_type)?;
+					}
+				}
+			}
+		}
+
 		debug!("Write statement: {}", &statement);
 		let mut stmt = self
 			.sqlite_connection
@@ -155,15 +665,62 @@ impl crate::output::SignalOutput for SignalOutputRaw {
 
 	fn write_attachment(
 		&mut self,
-		data: &[u8],
+		data: &mut dyn std::io::Read,
 		attachmend_id: u64,
 		row_id: u64,
-	) -> Result<(), anyhow::Error> {
-		self.write_to_file(
-			"attachment",
-			&format!("{}_{}", attachmend_id, row_id),
-			&data,
-		)?;
+	) -> Result<(), crate::error::Error> {
+		let (file, named, content_type) = match self.attachment_names.remove(&row_id) {
+			Some((file_name, content_type)) => {
+				let file = match attachment_file_name(
+					file_name.as_deref(),
+					content_type.as_deref(),
+					attachmend_id,
+					row_id,
+				) {
+					Some(x) => self.write_to_file("attachment", &x, data, false)?,
+					None => self.write_to_file(
+						"attachment",
+						&format!("{}_{}", attachmend_id, row_id),
+						data,
+						true,
+					)?,
+				};
+				(file, true, content_type)
+			}
+			// the row is read later, the file is renamed then
+			None => {
+				let file = self.write_to_file(
+					"attachment",
+					&format!("{}_{}", attachmend_id, row_id),
+					data,
+					true,
+				)?;
+				(file, false, None)
+			}
+		};
+
+		if let Some(ref object) = file.object {
+			self.sqlite_connection
+				.execute(
+					"INSERT INTO attachment_store VALUES (?, ?, ?, ?, ?)",
+					&[
+						&(attachmend_id as i64) as &dyn rusqlite::ToSql,
+						&(row_id as i64),
+						&object.hash,
+						&(object.size as i64),
+						&content_type.as_deref().or(file.mime),
+					],
+				)
+				.context("Failed to write to table of stored attachments")?;
+		}
+		self.attachments.insert(
+			row_id,
+			Attachment {
+				id: attachmend_id,
+				path: file.path,
+				named,
+			},
+		);
 
 		self.count_attachment += 1;
 		self.written_frames += 1;
@@ -171,7 +728,11 @@ impl crate::output::SignalOutput for SignalOutputRaw {
 		Ok(())
 	}
 
-	fn write_sticker(&mut self, data: &[u8], row_id: u64) -> Result<(), anyhow::Error> {
+	fn write_sticker(
+		&mut self,
+		data: &mut dyn std::io::Read,
+		row_id: u64,
+	) -> Result<(), crate::error::Error> {
 		//let mut path = self.path_sticker.join(format!("{}_{}", row_id, 1));
 		//if path.exists() {
 		//    path = self.path_sticker.join(format!("{}_{}", row_id, 2));
@@ -180,7 +741,8 @@ impl crate::output::SignalOutput for SignalOutputRaw {
 		self.write_to_file(
 			"sticker",
 			&format!("{}_{}", row_id, self.count_sticker),
-			&data,
+			data,
+			true,
 		)?;
 
 		self.count_sticker += 1;
@@ -189,13 +751,22 @@ impl crate::output::SignalOutput for SignalOutputRaw {
 		Ok(())
 	}
 
-	fn write_avatar(&mut self, data: &[u8], name: &str) -> Result<(), anyhow::Error> {
+	fn write_avatar(
+		&mut self,
+		data: &mut dyn std::io::Read,
+		name: &str,
+	) -> Result<(), crate::error::Error> {
 		//let mut path = self.path_sticker.join(format!("{}_{}", row_id, 1));
 		//if path.exists() {
 		//    path = self.path_sticker.join(format!("{}_{}", row_id, 2));
 		//}
 
-		self.write_to_file("avatar", &format!("{}_{}", name, self.count_avatar), &data)?;
+		self.write_to_file(
+			"avatar",
+			&format!("{}_{}", name, self.count_avatar),
+			data,
+			true,
+		)?;
 
 		self.count_avatar += 1;
 		self.written_frames += 1;
@@ -206,54 +777,99 @@ impl crate::output::SignalOutput for SignalOutputRaw {
 	fn write_preference(
 		&mut self,
 		pref: &crate::Backups::SharedPreference,
-	) -> Result<(), anyhow::Error> {
-		// create path to attachment file
-		let path = self.path_output.join("preference");
-		std::fs::create_dir_all(&path)
-			.with_context(|| format!("Failed to create path: {}", path.to_string_lossy()))?;
+	) -> Result<(), crate::error::Error> {
+		// file names may contain dots, the extension is appended
+		let file = match self.preference_format.extension() {
+			Some(x) => format!("{}.{}", pref.get_file(), x),
+			None => pref.get_file().to_string(),
+		};
+		let path = self.path_output.join("preference").join(file);
 
-		// open connection to file
-		let path = path.join(pref.get_file());
-		if path.exists() && !self.force_write && !self.created_files.contains(&path) {
-			return Err(anyhow!(
-				"Config file does already exist: {}. Try -f",
-				path.to_string_lossy()
-			));
+		// preferences are collected and written at the end, conflicts are found right away
+		if !self.preferences.contains_key(&path) {
+			if path.exists() && !self.force_write {
+				return Err(crate::error::Error::OutputConflict(path));
+			}
+			self.preferences.insert(path.clone(), Preferences::new());
 		}
+		self.preferences
+			.get_mut(&path)
+			.unwrap()
+			.insert(pref.get_key().to_string(), Preference::new(pref));
 
-		// write to file
-		let mut conf = ini::Ini::load_from_file(&path).unwrap_or_default();
-		conf.with_section(None::<String>)
-			.set(pref.get_key(), pref.get_value());
-		conf.write_to_file(&path).with_context(|| {
-			format!(
-				"Could not write to preference file: {}",
-				path.to_string_lossy()
-			)
-		})?;
-
-		self.created_files.insert(path);
 		self.written_frames += 1;
 
 		Ok(())
 	}
 
-	fn write_version(&mut self, version: u32) -> Result<(), anyhow::Error> {
+	fn write_version(&mut self, version: u32) -> Result<(), crate::error::Error> {
 		info!("Database Version: {:?}", version);
 		self.written_frames += 1;
 		Ok(())
 	}
 
-	fn write_key_value(&mut self, key_value: &crate::Backups::KeyValue) ->  Result<(), anyhow::Error>{
+	fn write_key_value(
+		&mut self,
+		key_value: &crate::Backups::KeyValue,
+	) -> Result<(), crate::error::Error> {
+		// values which are not set are written as NULL
+		self.sqlite_connection
+			.execute(
+				"INSERT INTO key_value VALUES (?, ?, ?, ?, ?, ?, ?)",
+				&[
+					&key_value.get_key() as &dyn rusqlite::ToSql,
+					&Some(key_value.get_blobValue()).filter(|_| key_value.has_blobValue()),
+					&Some(key_value.get_booleanValue()).filter(|_| key_value.has_booleanValue()),
+					&Some(f64::from(key_value.get_floatValue()))
+						.filter(|_| key_value.has_floatValue()),
+					&Some(key_value.get_integerValue()).filter(|_| key_value.has_integerValue()),
+					&Some(key_value.get_longValue()).filter(|_| key_value.has_longValue()),
+					&Some(key_value.get_stringValue()).filter(|_| key_value.has_stringValue()),
+				],
+			)
+			.with_context(|| format!("Failed to write key value pair: {}", key_value.get_key()))?;
+
+		if let Some(ref mut x) = self.key_values {
+			x.push(key_value.clone());
+		}
 		self.written_frames += 1;
 		Ok(())
 	}
 
+	// unknown frames are kept, so they can be inspected later
+	fn write_unknown(&mut self, data: &[u8], fields: &[u32]) -> Result<(), crate::error::Error> {
+		warn!(
+			"Unknown frame with fields {:?} written to directory unknown",
+			fields
+		);
+		self.write_to_file(
+			"unknown",
+			&self.count_unknown.to_string(),
+			&mut std::io::Cursor::new(data),
+			true,
+		)?;
+
+		self.count_unknown += 1;
+		self.written_frames += 1;
+
+		Ok(())
+	}
+
+	fn write_frame(&mut self, frame: crate::frame::Frame) -> Result<(), crate::error::Error> {
+		crate::output::write_frame_by_type(self, frame)
+	}
+
 	fn get_written_frames(&self) -> usize {
 		self.written_frames
 	}
 
-	fn finish(&mut self) -> Result<(), anyhow::Error> {
+	fn finish(&mut self) -> Result<(), crate::error::Error> {
+		self.write_attachment_list()?;
+		self.write_preferences()?;
+		if let Some(ref x) = self.key_values {
+			self.write_key_value_list(x)?;
+		}
+
 		if !self.sqlite_in_memory {
 			return Ok(());
 		}
@@ -275,3 +891,419 @@ impl crate::output::SignalOutput for SignalOutputRaw {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::output::SignalOutput;
+	use proptest::prelude::*;
+	use std::convert::TryFrom;
+
+	/// Decode statement with `parameter` like it is read from a backup and write it
+	fn write_statement(
+		output: &mut SignalOutputRaw,
+		statement: &str,
+		parameter: Vec<crate::Backups::SqlStatement_SqlParameter>,
+	) {
+		let mut sql = crate::Backups::SqlStatement::new();
+		sql.set_statement(statement.to_string());
+		sql.set_parameters(parameter.into());
+		let mut frame = crate::Backups::BackupFrame::new();
+		frame.set_statement(sql);
+
+		let data = protobuf::Message::write_to_bytes(&frame).unwrap();
+		output
+			.write_frame(crate::frame::Frame::try_from(data).unwrap())
+			.unwrap();
+	}
+
+	fn read_values(output: &SignalOutputRaw) -> Vec<rusqlite::types::Value> {
+		let mut stmt = output
+			.sqlite_connection
+			.prepare("SELECT v FROM t ORDER BY rowid")
+			.unwrap();
+		let values = stmt
+			.query_map(rusqlite::NO_PARAMS, |x| x.get(0))
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		values
+	}
+
+	fn open(name: &str) -> SignalOutputRaw {
+		let path =
+			std::env::temp_dir().join(format!("signal-backup-raw-{}-{}", name, std::process::id()));
+		let output =
+			SignalOutputRaw::new(&path, true, true, None, false, PreferenceFormat::Xml).unwrap();
+		std::fs::remove_dir_all(&path).unwrap();
+		output
+	}
+
+	#[test]
+	fn integer_values() {
+		let mut output = open("integer");
+		write_statement(&mut output, "CREATE TABLE t (v)", Vec::new());
+		for x in [0, 1, i64::MAX as u64, 1 << 63, u64::MAX].iter() {
+			let mut param = crate::Backups::SqlStatement_SqlParameter::new();
+			param.set_integerParameter(*x);
+			write_statement(&mut output, "INSERT INTO t VALUES (?)", vec![param]);
+		}
+
+		assert_eq!(
+			read_values(&output),
+			vec![
+				rusqlite::types::Value::Integer(0),
+				rusqlite::types::Value::Integer(1),
+				rusqlite::types::Value::Integer(i64::MAX),
+				rusqlite::types::Value::Integer(i64::MIN),
+				rusqlite::types::Value::Integer(-1),
+			]
+		);
+	}
+
+	#[test]
+	fn file_names() {
+		assert_eq!(
+			attachment_file_name(Some("report"), Some("application/pdf"), 1, 2).as_deref(),
+			Some("report.pdf")
+		);
+		assert_eq!(
+			attachment_file_name(Some("a.tar.gz"), Some("application/zip"), 1, 2).as_deref(),
+			Some("a.tar.gz")
+		);
+		assert_eq!(
+			attachment_file_name(None, Some("image/jpeg; charset=binary"), 1, 2).as_deref(),
+			Some("1_2.jpg")
+		);
+		assert_eq!(
+			attachment_file_name(Some("../../.bashrc"), None, 1, 2).as_deref(),
+			Some("bashrc")
+		);
+		assert_eq!(
+			attachment_file_name(Some("a:b\n.txt"), None, 1, 2).as_deref(),
+			Some("a_b_.txt")
+		);
+		assert_eq!(attachment_file_name(Some(".."), None, 1, 2), None);
+		assert_eq!(
+			attachment_file_name(None, Some("application/x-unknown"), 1, 2),
+			None
+		);
+	}
+
+	#[test]
+	fn attachments() {
+		let dir = crate::temp_dir::TempDir::new("raw-attachments");
+		let path = dir.path();
+		let mut output =
+			SignalOutputRaw::new(path, false, true, None, false, PreferenceFormat::Xml).unwrap();
+
+		let row =
+			|id: i64, content_type: &str, file_name: Option<&str>| crate::frame::Frame::Statement {
+				statement: "INSERT INTO part VALUES (?,?,?)".to_string(),
+				parameter: vec![
+					rusqlite::types::Value::Integer(id),
+					rusqlite::types::Value::Text(content_type.to_string()),
+					file_name.map_or(rusqlite::types::Value::Null, |x| {
+						rusqlite::types::Value::Text(x.to_string())
+					}),
+				],
+				unknown_fields: Default::default(),
+			};
+		let attachment = |row: u64| crate::frame::Frame::Attachment {
+			data_length: 1,
+			id: 10 + row,
+			row,
+			data: Some(Box::new(std::io::Cursor::new(vec![row as u8]))),
+			unknown_fields: Default::default(),
+		};
+
+		let frames = vec![
+			crate::frame::Frame::Statement {
+				statement: "CREATE TABLE part (_id INTEGER PRIMARY KEY, ct TEXT, file_name TEXT)"
+					.to_string(),
+				parameter: Vec::new(),
+				unknown_fields: Default::default(),
+			},
+			row(1, "application/pdf", Some("report")),
+			attachment(1),
+			// the row follows its attachment
+			attachment(2),
+			row(2, "image/jpeg", None),
+			row(3, "application/pdf", Some("report.pdf")),
+			attachment(3),
+			// the name guessed from the data is already right
+			crate::frame::Frame::Attachment {
+				data_length: 8,
+				id: 14,
+				row: 4,
+				data: Some(Box::new(std::io::Cursor::new(
+					b"\x89PNG\r\n\x1a\n".to_vec(),
+				))),
+				unknown_fields: Default::default(),
+			},
+			row(4, "image/png", None),
+		];
+		for frame in frames {
+			output.write_frame(frame).unwrap();
+		}
+		output.finish().unwrap();
+
+		let files = [("report.pdf", 1), ("12_2.jpg", 2), ("report (1).pdf", 3)];
+		for (name, content) in files.iter() {
+			assert_eq!(
+				std::fs::read(path.join("attachment").join(name)).unwrap(),
+				vec![*content]
+			);
+		}
+		assert_eq!(
+			std::fs::read_to_string(path.join(FILE_ATTACHMENTS)).unwrap(),
+			"attachment_id,row_id,path\n\
+			 11,1,attachment/report.pdf\n\
+			 12,2,attachment/12_2.jpg\n\
+			 13,3,attachment/report (1).pdf\n\
+			 14,4,attachment/14_4.png\n"
+		);
+	}
+
+	#[test]
+	fn store() {
+		let dir = crate::temp_dir::TempDir::new("raw-store");
+		let path = dir.path();
+		let path_store = path.join("store");
+		let mut output = SignalOutputRaw::new(
+			&path.join("out"),
+			false,
+			true,
+			Some(&path_store),
+			false,
+			PreferenceFormat::Xml,
+		)
+		.unwrap();
+
+		let frames = vec![
+			crate::frame::Frame::Statement {
+				statement: "CREATE TABLE part (_id INTEGER PRIMARY KEY, ct TEXT)".to_string(),
+				parameter: Vec::new(),
+				unknown_fields: Default::default(),
+			},
+			crate::frame::Frame::Statement {
+				statement: "INSERT INTO part VALUES (?,?)".to_string(),
+				parameter: vec![
+					rusqlite::types::Value::Integer(1),
+					rusqlite::types::Value::Text("text/plain".to_string()),
+				],
+				unknown_fields: Default::default(),
+			},
+		];
+		for frame in frames {
+			output.write_frame(frame).unwrap();
+		}
+		// both attachments have the same content
+		for row in 1..3 {
+			output
+				.write_frame(crate::frame::Frame::Attachment {
+					data_length: 5,
+					id: 10 + row,
+					row,
+					data: Some(Box::new(std::io::Cursor::new(b"hello".to_vec()))),
+					unknown_fields: Default::default(),
+				})
+				.unwrap();
+		}
+
+		let hash = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+		let mut stmt = output
+			.sqlite_connection
+			.prepare("SELECT * FROM attachment_store ORDER BY row_id")
+			.unwrap();
+		let rows: Vec<(i64, i64, String, i64, Option<String>)> = stmt
+			.query_map(rusqlite::NO_PARAMS, |x| {
+				Ok((x.get(0)?, x.get(1)?, x.get(2)?, x.get(3)?, x.get(4)?))
+			})
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		assert_eq!(
+			rows,
+			vec![
+				(11, 1, hash.to_string(), 5, Some("text/plain".to_string())),
+				(12, 2, hash.to_string(), 5, None),
+			]
+		);
+
+		assert_eq!(std::fs::read_dir(&path_store).unwrap().count(), 1);
+		for name in ["11_1.txt", "12_2"].iter() {
+			assert_eq!(
+				std::fs::read(path.join("out").join("attachment").join(name)).unwrap(),
+				b"hello"
+			);
+		}
+	}
+
+	#[test]
+	fn key_values() {
+		let dir = crate::temp_dir::TempDir::new("raw-key-value");
+		let path = dir.path();
+		let mut output =
+			SignalOutputRaw::new(path, false, true, None, true, PreferenceFormat::Xml).unwrap();
+
+		let mut a = crate::Backups::KeyValue::new();
+		a.set_key("a".to_string());
+		a.set_longValue(1 << 40);
+		let mut b = crate::Backups::KeyValue::new();
+		b.set_key("b".to_string());
+		b.set_booleanValue(false);
+		let mut c = crate::Backups::KeyValue::new();
+		c.set_key("c".to_string());
+		c.set_blobValue(vec![1, 2]);
+		for key_value in [a, b, c].iter() {
+			output.write_key_value(key_value).unwrap();
+		}
+		output.finish().unwrap();
+
+		let mut stmt = output
+			.sqlite_connection
+			.prepare("SELECT * FROM key_value ORDER BY key")
+			.unwrap();
+		let rows: Vec<Vec<rusqlite::types::Value>> = stmt
+			.query_map(rusqlite::NO_PARAMS, |x| (0..7).map(|i| x.get(i)).collect())
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		let null = rusqlite::types::Value::Null;
+		assert_eq!(
+			rows,
+			vec![
+				vec![
+					rusqlite::types::Value::Text("a".to_string()),
+					null.clone(),
+					null.clone(),
+					null.clone(),
+					null.clone(),
+					rusqlite::types::Value::Integer(1 << 40),
+					null.clone(),
+				],
+				vec![
+					rusqlite::types::Value::Text("b".to_string()),
+					null.clone(),
+					rusqlite::types::Value::Integer(0),
+					null.clone(),
+					null.clone(),
+					null.clone(),
+					null.clone(),
+				],
+				vec![
+					rusqlite::types::Value::Text("c".to_string()),
+					rusqlite::types::Value::Blob(vec![1, 2]),
+					null.clone(),
+					null.clone(),
+					null.clone(),
+					null.clone(),
+					null,
+				],
+			]
+		);
+
+		let json: serde_json::Value =
+			serde_json::from_slice(&std::fs::read(path.join(FILE_KEY_VALUE)).unwrap()).unwrap();
+		assert_eq!(
+			json,
+			serde_json::json!([
+				{"key": "a", "longValue": 1u64 << 40},
+				{"key": "b", "booleanValue": false},
+				{"key": "c", "blobValue": "AQI="},
+			])
+		);
+	}
+
+	#[test]
+	fn preferences() {
+		let preference = |key: &str, value: Preference| {
+			let mut pref = crate::Backups::SharedPreference::new();
+			pref.set_file("org.thoughtcrime.securesms_preferences".to_string());
+			pref.set_key(key.to_string());
+			match value {
+				Preference::String(x) => pref.set_value(x),
+				Preference::Boolean(x) => pref.set_booleanValue(x),
+				Preference::StringSet(x) => {
+					pref.set_stringSetValue(x.into());
+					pref.set_isStringSetValue(true);
+				}
+			}
+			pref
+		};
+		let prefs = [
+			preference("b", Preference::Boolean(false)),
+			preference("a", Preference::String("x < y".to_string())),
+			preference(
+				"c",
+				Preference::StringSet(vec!["1".to_string(), "2".to_string()]),
+			),
+			// an empty string set is no string
+			preference("d", Preference::StringSet(Vec::new())),
+		];
+
+		let files = [
+			(
+				PreferenceFormat::Xml,
+				"org.thoughtcrime.securesms_preferences.xml",
+				"<?xml version='1.0' encoding='utf-8' standalone='yes' ?>\n\
+				 <map>\n    \
+				 <string name=\"a\">x &lt; y</string>\n    \
+				 <boolean name=\"b\" value=\"false\" />\n    \
+				 <set name=\"c\">\n        \
+				 <string>1</string>\n        \
+				 <string>2</string>\n    \
+				 </set>\n    \
+				 <set name=\"d\">\n    \
+				 </set>\n\
+				 </map>\n",
+			),
+			(
+				PreferenceFormat::Ini,
+				"org.thoughtcrime.securesms_preferences",
+				"a=x < y\nb=false\nc=[\"1\",\"2\"]\nd=[]\n",
+			),
+			(
+				PreferenceFormat::Json,
+				"org.thoughtcrime.securesms_preferences.json",
+				"{\n  \"a\": \"x < y\",\n  \"b\": false,\n  \"c\": [\n    \"1\",\n    \"2\"\n  ],\n  \"d\": []\n}",
+			),
+		];
+		for (format, name, content) in files.iter() {
+			let dir = crate::temp_dir::TempDir::new(&format!("raw-preferences-{:?}", format));
+			let path = dir.path();
+			let mut output = SignalOutputRaw::new(path, false, true, None, false, *format).unwrap();
+			for pref in prefs.iter() {
+				output.write_preference(pref).unwrap();
+			}
+			output.finish().unwrap();
+
+			assert_eq!(
+				std::fs::read_to_string(path.join("preference").join(name)).unwrap(),
+				*content
+			);
+		}
+	}
+
+	proptest! {
+		#![proptest_config(ProptestConfig::with_cases(32))]
+
+		#[test]
+		fn database_values(
+			values in proptest::collection::vec(crate::frame::tests::value(), 0..20)
+		) {
+			let mut output = open("values");
+			write_statement(&mut output, "CREATE TABLE t (v)", Vec::new());
+			for value in values.iter() {
+				write_statement(
+					&mut output,
+					"INSERT INTO t VALUES (?)",
+					vec![crate::frame::value_to_parameter(value)],
+				);
+			}
+
+			prop_assert_eq!(read_values(&output), values);
+		}
+	}
+}
@@ -0,0 +1,318 @@
+use log::error;
+use std::io::{Read, Write};
+
+/// Size of the chunks attached data is passed on in
+const LENGTH_CHUNK: usize = 64 * 1024;
+
+/// Write several outputs in a single pass
+///
+/// Every frame is passed to all outputs. Attached data is read once and streamed to the outputs,
+/// which run in their own threads while the data is passed on.
+///
+/// If an output fails, the error is logged together with the name of the output and the other
+/// outputs are continued. The first error is returned by `finish`, or as soon as all outputs have
+/// failed. If the attached data itself cannot be read, all outputs are kept and the read error is
+/// returned.
+pub struct SignalOutputTee {
+	outputs: Vec<(String, Box<dyn crate::output::SignalOutput>)>,
+	error: Option<crate::error::Error>,
+}
+
+impl SignalOutputTee {
+	/// Creates new output object
+	///
+	/// `outputs` are the outputs to write together with a name used in error messages.
+	pub fn new(outputs: Vec<(String, Box<dyn crate::output::SignalOutput>)>) -> Self {
+		Self {
+			outputs,
+			error: None,
+		}
+	}
+
+	/// Pass frame with attached data `data` to all outputs
+	fn write_frame_with_data(
+		&mut self,
+		frame: crate::frame::Frame,
+		data: &mut dyn Read,
+	) -> Result<Vec<Result<(), crate::error::Error>>, crate::error::Error> {
+		let frames = copies(frame, self.outputs.len());
+
+		std::thread::scope(|scope| {
+			let mut writers = Vec::with_capacity(frames.len());
+			let threads: Vec<_> = self
+				.outputs
+				.iter_mut()
+				.zip(frames)
+				.map(|((_, output), mut frame)| {
+					let (pipe_writer, pipe_reader) = crate::pipe::pipe();
+					frame.set_data(Box::new(pipe_reader));
+					writers.push(pipe_writer);
+					scope.spawn(move || output.write_frame(frame))
+				})
+				.collect();
+
+			let result = copy_to_pipes(data, writers);
+			let results = threads.into_iter().map(|x| x.join().unwrap()).collect();
+			result.map(|_| results)
+		})
+	}
+
+	/// Remove outputs which failed and keep the first error
+	fn check(&mut self, results: Vec<Result<(), crate::error::Error>>) {
+		for ((name, output), result) in std::mem::take(&mut self.outputs).into_iter().zip(results) {
+			match result {
+				Ok(()) => self.outputs.push((name, output)),
+				Err(e) => self.fail(&name, e),
+			}
+		}
+	}
+
+	fn fail(&mut self, name: &str, error: crate::error::Error) {
+		error!("Output {} failed: {}.", name, error);
+		if self.error.is_none() {
+			self.error = Some(error);
+		}
+	}
+}
+
+/// Copy the frame `count` times, the last copy is `frame` itself
+fn copies(frame: crate::frame::Frame, count: usize) -> Vec<crate::frame::Frame> {
+	let mut frames: Vec<_> = (1..count).map(|_| frame.copy_without_data()).collect();
+	frames.push(frame);
+	frames
+}
+
+/// Pass `data` to all `writers`
+///
+/// A read error is passed to all writers and returned. Writers whose reading end has been
+/// dropped are skipped silently.
+fn copy_to_pipes(
+	data: &mut dyn Read,
+	mut writers: Vec<crate::pipe::PipeWriter>,
+) -> Result<(), crate::error::Error> {
+	let mut buffer = vec![0u8; LENGTH_CHUNK];
+	loop {
+		match data.read(&mut buffer) {
+			Ok(0) => return Ok(()),
+			Ok(count) => {
+				for writer in writers.iter_mut() {
+					// writing to a pipe does not fail
+					writer.write_all(&buffer[..count]).unwrap();
+				}
+			}
+			Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+			Err(e) => {
+				let error = crate::error::Error::from(e);
+				for writer in writers {
+					writer.fail(&error);
+				}
+				return Err(error);
+			}
+		}
+	}
+}
+
+impl crate::output::SignalOutput for SignalOutputTee {
+	/// Frames written by all remaining outputs
+	fn get_written_frames(&self) -> usize {
+		self.outputs
+			.iter()
+			.map(|(_, output)| output.get_written_frames())
+			.min()
+			.unwrap_or(0)
+	}
+
+	fn write_frame(&mut self, mut frame: crate::frame::Frame) -> Result<(), crate::error::Error> {
+		let results = match frame.take_data() {
+			Some(mut data) => self.write_frame_with_data(frame, &mut data)?,
+			None => {
+				let frames = copies(frame, self.outputs.len());
+				self.outputs
+					.iter_mut()
+					.zip(frames)
+					.map(|((_, output), frame)| output.write_frame(frame))
+					.collect()
+			}
+		};
+		self.check(results);
+
+		// there is no reason to continue without outputs
+		if self.outputs.is_empty() {
+			if let Some(e) = self.error.take() {
+				return Err(e);
+			}
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<(), crate::error::Error> {
+		let results = self
+			.outputs
+			.iter_mut()
+			.map(|(_, output)| output.finish())
+			.collect();
+		self.check(results);
+
+		match self.error.take() {
+			Some(e) => Err(e),
+			None => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::output::SignalOutput;
+
+	/// Output collecting the attached data of all frames
+	struct Collect {
+		data: std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>>,
+		written_frames: usize,
+		fail: bool,
+	}
+
+	impl crate::output::SignalOutput for Collect {
+		fn write_frame(
+			&mut self,
+			mut frame: crate::frame::Frame,
+		) -> Result<(), crate::error::Error> {
+			if let Some(mut data) = frame.take_data() {
+				if self.fail {
+					return Err(crate::error::Error::Output("failed".to_string()));
+				}
+				let mut buffer = Vec::new();
+				data.read_to_end(&mut buffer)?;
+				self.data.lock().unwrap().push(buffer);
+			}
+			self.written_frames += 1;
+			Ok(())
+		}
+
+		fn get_written_frames(&self) -> usize {
+			self.written_frames
+		}
+
+		fn finish(&mut self) -> Result<(), crate::error::Error> {
+			Ok(())
+		}
+	}
+
+	fn attachment(data: Vec<u8>) -> crate::frame::Frame {
+		crate::frame::Frame::Attachment {
+			data_length: data.len(),
+			id: 1,
+			row: 1,
+			data: Some(Box::new(std::io::Cursor::new(data))),
+			unknown_fields: Default::default(),
+		}
+	}
+
+	#[test]
+	fn write_all() {
+		let data = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+		let mut output = SignalOutputTee::new(vec![
+			(
+				"a".to_string(),
+				Box::new(Collect {
+					data: data.clone(),
+					written_frames: 1,
+					fail: false,
+				}),
+			),
+			(
+				"b".to_string(),
+				Box::new(Collect {
+					data: data.clone(),
+					written_frames: 1,
+					fail: false,
+				}),
+			),
+		]);
+
+		let attached: Vec<u8> = (0..200_000).map(|x| x as u8).collect();
+		output.write_frame(attachment(attached.clone())).unwrap();
+		output
+			.write_frame(crate::frame::Frame::Statement {
+				statement: "SELECT 1".to_string(),
+				parameter: Vec::new(),
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		// passed on as frame by default
+		output.write_sticker(&mut &b"abc"[..], 1).unwrap();
+		assert_eq!(output.get_written_frames(), 4);
+		output.finish().unwrap();
+
+		assert_eq!(
+			*data.lock().unwrap(),
+			vec![attached.clone(), attached, b"abc".to_vec(), b"abc".to_vec()]
+		);
+	}
+
+	#[test]
+	fn write_failed() {
+		let data = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+		let mut output = SignalOutputTee::new(vec![
+			(
+				"a".to_string(),
+				Box::new(Collect {
+					data: data.clone(),
+					written_frames: 1,
+					fail: true,
+				}),
+			),
+			(
+				"b".to_string(),
+				Box::new(Collect {
+					data: data.clone(),
+					written_frames: 1,
+					fail: false,
+				}),
+			),
+		]);
+
+		// the other output is continued, the error is returned at the end
+		output.write_frame(attachment(vec![1, 2, 3])).unwrap();
+		output.write_frame(attachment(vec![4, 5])).unwrap();
+		assert_eq!(output.get_written_frames(), 3);
+		assert!(output.finish().is_err());
+		assert_eq!(*data.lock().unwrap(), vec![vec![1, 2, 3], vec![4, 5]]);
+	}
+
+	/// Attached data which cannot be read
+	struct Broken;
+
+	impl std::io::Read for Broken {
+		fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+			Err(std::io::Error::other("broken"))
+		}
+	}
+
+	#[test]
+	fn read_failed() {
+		let data = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+		let mut output = SignalOutputTee::new(vec![(
+			"a".to_string(),
+			Box::new(Collect {
+				data: data.clone(),
+				written_frames: 1,
+				fail: false,
+			}),
+		)]);
+
+		// the error of the input is returned, the output is kept
+		assert!(output
+			.write_frame(crate::frame::Frame::Attachment {
+				data_length: 3,
+				id: 1,
+				row: 1,
+				data: Some(Box::new(Broken)),
+				unknown_fields: Default::default(),
+			})
+			.is_err());
+		output.write_frame(attachment(vec![4, 5])).unwrap();
+		output.finish().unwrap();
+		assert_eq!(*data.lock().unwrap(), vec![vec![4, 5]]);
+	}
+}
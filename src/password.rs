@@ -0,0 +1,267 @@
+use crate::error::Context;
+use log::info;
+use std::io::Read;
+use zeroize::Zeroizing;
+
+/// Length of backup passwords in digits
+pub const LENGTH_PASSWORD: usize = 30;
+
+/// Parse a backup password, ignoring everything except digits (e.g. the spaces between groups)
+pub fn parse(password: &str) -> Result<Zeroizing<Vec<u8>>, crate::error::Error> {
+	let mut digits = Zeroizing::new(Vec::with_capacity(password.len()));
+	digits.extend(password.bytes().filter(u8::is_ascii_digit));
+	if digits.len() != LENGTH_PASSWORD {
+		return Err(crate::error::Error::Config(
+			"Wrong password length (30 numeric characters are expected)".to_string(),
+		));
+	}
+
+	Ok(digits)
+}
+
+/// Number of bytes read from the start of the backup to check passwords
+///
+/// This has to contain the header and the first encrypted frame.
+const LENGTH_PREFIX: u64 = 1 << 20;
+
+/// Pattern of a partially known backup password
+///
+/// Digits are fixed, `?` stands for an unknown digit and `(12345|12354)` for a group with
+/// several candidates of the same length. Whitespace is ignored.
+pub struct Pattern {
+	/// Candidates for each part of the password
+	parts: Vec<Vec<Vec<u8>>>,
+}
+
+impl Pattern {
+	/// Parse password pattern `pattern`
+	pub fn parse(pattern: &str) -> Result<Self, crate::error::Error> {
+		let mut parts = Vec::new();
+		let mut chars = pattern.chars().filter(|c| !c.is_whitespace());
+
+		while let Some(c) = chars.next() {
+			match c {
+				'0'..='9' => parts.push(vec![vec![c as u8]]),
+				'?' => parts.push((b'0'..=b'9').map(|x| vec![x]).collect()),
+				'(' => {
+					let mut group = vec![Vec::new()];
+					loop {
+						match chars.next() {
+							Some(x @ '0'..='9') => group.last_mut().unwrap().push(x as u8),
+							Some('|') => group.push(Vec::new()),
+							Some(')') => break,
+							_ => {
+								return Err(crate::error::Error::Config(
+									"Unfinished group in password pattern".to_string(),
+								))
+							}
+						}
+					}
+					if group.iter().any(|x| x.len() != group[0].len()) {
+						return Err(crate::error::Error::Config(
+							"All candidates of a group in the password pattern need the same length"
+								.to_string(),
+						));
+					}
+					parts.push(group);
+				}
+				x => {
+					return Err(crate::error::Error::Config(format!(
+						"Invalid character in password pattern: {}",
+						x
+					)))
+				}
+			}
+		}
+
+		let length: usize = parts.iter().map(|x| x[0].len()).sum();
+		if length != LENGTH_PASSWORD {
+			return Err(crate::error::Error::Config(format!(
+				"Wrong password pattern length ({} digits are expected, got {})",
+				LENGTH_PASSWORD, length
+			)));
+		}
+
+		Ok(Self { parts })
+	}
+
+	/// Number of passwords matching the pattern, `None` if it does not fit into `u64`
+	pub fn count(&self) -> Option<u64> {
+		self.parts
+			.iter()
+			.try_fold(1u64, |acc, x| acc.checked_mul(x.len() as u64))
+	}
+
+	/// Return the password with the given `index`, which has to be less than `count`
+	pub fn candidate(&self, mut index: u64) -> Zeroizing<Vec<u8>> {
+		let mut parts = Vec::with_capacity(self.parts.len());
+		for part in self.parts.iter().rev() {
+			let count = part.len() as u64;
+			parts.push(&part[(index % count) as usize]);
+			index /= count;
+		}
+
+		let mut password = Zeroizing::new(Vec::with_capacity(LENGTH_PASSWORD));
+		password.extend(parts.into_iter().rev().flatten());
+		password
+	}
+}
+
+/// Read the start of the backup at `path`, `-` reads from stdin
+pub fn read_prefix(path: &std::path::Path) -> Result<Vec<u8>, crate::error::Error> {
+	let reader: Box<dyn Read> =
+		if path == std::path::Path::new("-") {
+			Box::new(std::io::stdin())
+		} else {
+			Box::new(std::fs::File::open(path).with_context(|| {
+				format!("Could not open backup file: {}", path.to_string_lossy())
+			})?)
+		};
+
+	let mut prefix = Vec::new();
+	reader
+		.take(LENGTH_PREFIX)
+		.read_to_end(&mut prefix)
+		.context("Could not read backup")?;
+	Ok(prefix)
+}
+
+/// Search the password matching `pattern` for the backup starting with `prefix`
+///
+/// Every candidate is checked by decrypting the first frame and verifying its MAC. The
+/// candidates are split between `threads` threads. If `hidden` is set, no progress bar is shown.
+pub fn recover(
+	prefix: &[u8],
+	pattern: &Pattern,
+	limits: crate::input::Limits,
+	threads: usize,
+	hidden: bool,
+) -> Result<Option<Zeroizing<Vec<u8>>>, crate::error::Error> {
+	let count = pattern.count().ok_or_else(|| {
+		crate::error::Error::Config("Password pattern has too many candidates".to_string())
+	})?;
+
+	// estimate runtime based on a single key derivation
+	let start = std::time::Instant::now();
+	crate::decrypter::derive_keys(&[b'0'; LENGTH_PASSWORD], &[0; 32]);
+	let estimate = start.elapsed().as_secs_f64() * count as f64 / threads as f64;
+	info!(
+		"Trying {} passwords on {} threads, this takes up to {}",
+		count,
+		threads,
+		indicatif::HumanDuration(std::time::Duration::from_secs_f64(estimate))
+	);
+
+	let progress = if hidden {
+		indicatif::ProgressBar::hidden()
+	} else {
+		indicatif::ProgressBar::new(count)
+	};
+	progress.set_style(
+		indicatif::ProgressStyle::default_bar()
+			.template("Checked passwords: [{elapsed_precise}] [{bar:50.blue/blue}] {pos}/{len} (eta {eta})")
+			.progress_chars("#>-"),
+	);
+
+	let next = std::sync::atomic::AtomicU64::new(0);
+	let found = std::sync::Mutex::new(None);
+	let result = std::thread::scope(|scope| {
+		let workers: Vec<_> = (0..threads)
+			.map(|_| {
+				scope.spawn(|| -> Result<(), crate::error::Error> {
+					loop {
+						let index = next.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+						if index >= count || found.lock().unwrap().is_some() {
+							return Ok(());
+						}
+
+						let candidate = pattern.candidate(index);
+						if check(prefix, &candidate, limits)? {
+							*found.lock().unwrap() = Some(candidate);
+							return Ok(());
+						}
+						progress.inc(1);
+					}
+				})
+			})
+			.collect();
+
+		workers.into_iter().try_for_each(|x| x.join().unwrap())
+	});
+	progress.finish_and_clear();
+
+	result?;
+	Ok(found.into_inner().unwrap())
+}
+
+/// Check whether `password` decrypts the first frame of the backup starting with `prefix`
+fn check(
+	prefix: &[u8],
+	password: &Zeroizing<Vec<u8>>,
+	limits: crate::input::Limits,
+) -> Result<bool, crate::error::Error> {
+	// errors while opening are caused by the header and thus the same for every password
+	let mut reader = crate::input::InputFile::new(
+		std::io::Cursor::new(prefix),
+		None,
+		&crate::key::Secret::Password(password.clone()),
+		crate::input::InputOptions {
+			limits,
+			..Default::default()
+		},
+	)?;
+
+	Ok(reader.read_frame().is_ok())
+}
+
+/// Format `password` in groups of five digits, like Signal displays it
+pub fn format(password: &[u8]) -> String {
+	password
+		.chunks(5)
+		.map(|x| String::from_utf8_lossy(x))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pattern() {
+		let pattern = Pattern::parse("12345 12345 12345 12345 12345 123??").unwrap();
+		assert_eq!(pattern.count(), Some(100));
+		assert_eq!(*pattern.candidate(0), b"123451234512345123451234512300");
+		assert_eq!(*pattern.candidate(42), b"123451234512345123451234512342");
+
+		let pattern = Pattern::parse("(12345|54321) 12345 12345 12345 12345 1234?").unwrap();
+		assert_eq!(pattern.count(), Some(20));
+		assert_eq!(*pattern.candidate(13), b"543211234512345123451234512343");
+
+		assert!(Pattern::parse("12345").is_err());
+		assert!(Pattern::parse("(1|23) 12345 12345 12345 12345 12345 1234").is_err());
+		assert!(Pattern::parse("(12345 12345 12345 12345 12345 12345").is_err());
+		assert!(Pattern::parse("a2345 12345 12345 12345 12345 12345").is_err());
+	}
+
+	#[test]
+	fn recover_password() {
+		let password = b"123451234512345123451234512343";
+		let mut encoder = crate::encoder::Encoder::new(Vec::new(), password, 1).unwrap();
+		encoder
+			.write_frame(crate::frame::Frame::Version {
+				version: 57,
+				unknown_fields: Default::default(),
+			})
+			.unwrap();
+		let backup = encoder.finish().unwrap();
+
+		let pattern = Pattern::parse("12345 12345 12345 12345 12345 1234(0|3)").unwrap();
+		let found = recover(&backup, &pattern, crate::input::Limits::default(), 4, true).unwrap();
+		assert_eq!(found.as_deref(), Some(&password.to_vec()));
+
+		let pattern = Pattern::parse("12345 12345 12345 12345 12345 1235(0|1)").unwrap();
+		let found = recover(&backup, &pattern, crate::input::Limits::default(), 4, true).unwrap();
+		assert!(found.is_none());
+	}
+}
@@ -0,0 +1,89 @@
+/// Number of chunks buffered in a pipe
+const PIPE_CAPACITY: usize = 4;
+
+type Chunk = Result<Vec<u8>, std::io::Error>;
+
+/// Create a pipe to stream attached data from the input to the output thread
+///
+/// Only `PIPE_CAPACITY` chunks are buffered, writing blocks until the reader has caught up.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+	let (tx, rx) = std::sync::mpsc::sync_channel(PIPE_CAPACITY);
+
+	(
+		PipeWriter { tx },
+		PipeReader {
+			rx,
+			chunk: Vec::new(),
+			position: 0,
+			failed: std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false)),
+		},
+	)
+}
+
+/// Writing end of a pipe
+///
+/// If the reading end is dropped, e.g. because an output does not need the data, all data is
+/// silently discarded.
+pub struct PipeWriter {
+	tx: std::sync::mpsc::SyncSender<Chunk>,
+}
+
+impl PipeWriter {
+	/// Close the pipe with an error, which is returned to the reader
+	pub fn fail(self, error: &crate::error::Error) {
+		let _ = self.tx.send(Err(std::io::Error::other(error.to_string())));
+	}
+}
+
+impl std::io::Write for PipeWriter {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		let _ = self.tx.send(Ok(buf.to_vec()));
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Reading end of a pipe
+///
+/// EOF is returned after the writing end has been dropped.
+pub struct PipeReader {
+	rx: std::sync::mpsc::Receiver<Chunk>,
+	chunk: Vec<u8>,
+	position: usize,
+	failed: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl PipeReader {
+	/// Return a flag which is set as soon as the error of a failed writing end has been read
+	///
+	/// The flag stays available after the reader has been passed on, e.g. to an output.
+	pub fn failed(&self) -> std::sync::Arc<std::sync::atomic::AtomicBool> {
+		self.failed.clone()
+	}
+}
+
+impl std::io::Read for PipeReader {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		while self.position == self.chunk.len() {
+			match self.rx.recv() {
+				Ok(Ok(chunk)) => {
+					self.chunk = chunk;
+					self.position = 0;
+				}
+				Ok(Err(e)) => {
+					self.failed.store(true, std::sync::atomic::Ordering::SeqCst);
+					return Err(e);
+				}
+				Err(_) => return Ok(0),
+			}
+		}
+
+		let count = std::cmp::min(buf.len(), self.chunk.len() - self.position);
+		buf[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+		self.position += count;
+		Ok(count)
+	}
+}
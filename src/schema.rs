@@ -0,0 +1,188 @@
+//! Columns of the tables in backups
+//!
+//! Signal writes rows as `INSERT INTO table VALUES (...)` without column names. The columns are
+//! read from the `CREATE TABLE` statements, which are written before the rows.
+
+/// Tables containing attachments of messages, `part` before database version 181
+pub const TABLES_ATTACHMENT: [&str; 2] = ["part", "attachment"];
+
+/// Columns of the tables created so far
+#[derive(Default)]
+pub struct Tables {
+	columns: std::collections::HashMap<String, Vec<String>>,
+}
+
+impl Tables {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Remember the columns of the table created by `statement`
+	///
+	/// Returns whether `statement` creates a table.
+	pub fn read_statement(&mut self, statement: &str) -> bool {
+		match parse_create_table(statement) {
+			Some((table, columns)) => {
+				self.columns.insert(table, columns);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Row inserted by `statement` with `values`
+	///
+	/// Returns `None` if `statement` is no insert or the columns of the table are unknown.
+	pub fn row<'a>(
+		&'a self,
+		statement: &'a str,
+		values: &'a [rusqlite::types::Value],
+	) -> Option<Row<'a>> {
+		let table = parse_insert(statement)?;
+		Some(Row {
+			table,
+			columns: self.columns.get(table)?,
+			values,
+		})
+	}
+}
+
+/// Row of a table with known columns
+pub struct Row<'a> {
+	pub table: &'a str,
+	columns: &'a [String],
+	values: &'a [rusqlite::types::Value],
+}
+
+impl Row<'_> {
+	/// Index of column `name` in the values
+	pub fn position(&self, name: &str) -> Option<usize> {
+		self.columns.iter().position(|x| x == name)
+	}
+
+	/// Value of column `name`
+	pub fn get(&self, name: &str) -> Option<&rusqlite::types::Value> {
+		self.position(name).and_then(|i| self.values.get(i))
+	}
+
+	/// Value of the first existing column of `names`
+	pub fn get_first(&self, names: &[&str]) -> Option<&rusqlite::types::Value> {
+		names.iter().find_map(|x| self.get(x))
+	}
+
+	/// Integer value of column `name`
+	pub fn get_integer(&self, name: &str) -> Option<i64> {
+		match self.get(name) {
+			Some(rusqlite::types::Value::Integer(x)) => Some(*x),
+			_ => None,
+		}
+	}
+
+	/// Text value of the first existing column of `names`
+	pub fn get_text(&self, names: &[&str]) -> Option<&str> {
+		match self.get_first(names) {
+			Some(rusqlite::types::Value::Text(x)) => Some(x),
+			_ => None,
+		}
+	}
+}
+
+/// Parse name and columns of the table created by `statement`
+///
+/// Returns `None` if `statement` does not create a table.
+fn parse_create_table(statement: &str) -> Option<(String, Vec<String>)> {
+	let definition = statement.strip_prefix("CREATE TABLE ")?;
+	let start = definition.find('(')?;
+	let end = definition.rfind(')')?;
+	let name = unquote(definition[..start].trim());
+
+	// split column definitions at commas outside of parentheses
+	let mut columns = Vec::new();
+	let mut depth = 0;
+	let mut column = String::new();
+	for c in definition[start + 1..end]
+		.chars()
+		.chain(std::iter::once(','))
+	{
+		match c {
+			'(' => depth += 1,
+			')' => depth -= 1,
+			',' if depth == 0 => {
+				if let Some(x) = column
+					.split(|c: char| c.is_whitespace() || c == '(')
+					.find(|x| !x.is_empty())
+				{
+					// skip table constraints
+					if !["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+						.contains(&x.to_uppercase().as_str())
+					{
+						columns.push(unquote(x).to_string());
+					}
+				}
+				column.clear();
+				continue;
+			}
+			_ => (),
+		}
+		column.push(c);
+	}
+
+	Some((name.to_string(), columns))
+}
+
+/// Parse the name of the table `statement` inserts into
+pub fn parse_insert(statement: &str) -> Option<&str> {
+	let rest = statement.strip_prefix("INSERT INTO ")?;
+	rest.split_whitespace().next().map(unquote)
+}
+
+/// Remove quotes around identifiers
+fn unquote(name: &str) -> &str {
+	name.trim_matches(|c| c == '"' || c == '`' || c == '[' || c == ']')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn create_table() {
+		assert_eq!(
+			parse_create_table(
+				"CREATE TABLE \"part\" (_id INTEGER PRIMARY KEY, mid INTEGER, data_size INTEGER DEFAULT 0, \
+				 ct TEXT, UNIQUE(mid, ct) ON CONFLICT REPLACE)"
+			),
+			Some((
+				"part".to_string(),
+				vec![
+					"_id".to_string(),
+					"mid".to_string(),
+					"data_size".to_string(),
+					"ct".to_string()
+				]
+			))
+		);
+		assert_eq!(parse_create_table("CREATE INDEX x ON part (mid)"), None);
+		assert_eq!(parse_insert("INSERT INTO mms VALUES (?,?)"), Some("mms"));
+	}
+
+	#[test]
+	fn row() {
+		let mut tables = Tables::new();
+		assert!(tables.read_statement("CREATE TABLE part (_id INTEGER, ct TEXT)"));
+		assert!(!tables.read_statement("INSERT INTO part VALUES (?,?)"));
+
+		let values = vec![
+			rusqlite::types::Value::Integer(1),
+			rusqlite::types::Value::Text("image/png".to_string()),
+		];
+		let row = tables
+			.row("INSERT INTO part VALUES (?,?)", &values)
+			.unwrap();
+		assert_eq!(row.table, "part");
+		assert_eq!(row.get_integer("_id"), Some(1));
+		assert_eq!(row.get_text(&["content_type", "ct"]), Some("image/png"));
+		assert_eq!(row.get("mid"), None);
+		assert!(tables.row("INSERT INTO mms VALUES (?)", &values).is_none());
+	}
+}
@@ -0,0 +1,203 @@
+use crate::error::Context;
+use sha2::Digest;
+use std::io::Write;
+
+/// Directory storing files once under their SHA-256 hash
+///
+/// Files are written to `<hash prefix>/<hash>`, where the prefix are the first two hex digits.
+/// Writing a file which is already stored, e.g. by an earlier run, only returns its path. The
+/// files are linked to their locations in the output with `link`.
+pub struct Store {
+	path: std::path::PathBuf,
+	count_temp: usize,
+}
+
+/// File in a store
+pub struct Object {
+	/// Path of the file in the store
+	pub path: std::path::PathBuf,
+	/// SHA-256 hash as hex string
+	pub hash: String,
+	/// Size in bytes
+	pub size: u64,
+}
+
+impl Store {
+	/// Open the store at `path`, it is created if it does not exist
+	pub fn open(path: &std::path::Path) -> Result<Self, crate::error::Error> {
+		std::fs::create_dir_all(path)
+			.with_context(|| format!("Store could not be created: {}", path.to_string_lossy()))?;
+
+		Ok(Self {
+			path: path.to_path_buf(),
+			count_temp: 0,
+		})
+	}
+
+	/// Store the file consisting of `head` followed by `data`
+	///
+	/// The data is written to a temporary file while its hash is computed and moved into the
+	/// store afterwards, so incomplete files are never visible.
+	pub fn write(
+		&mut self,
+		head: &[u8],
+		data: &mut dyn std::io::Read,
+	) -> Result<Object, crate::error::Error> {
+		let path_temp = self
+			.path
+			.join(format!(".tmp-{}-{}", std::process::id(), self.count_temp));
+		self.count_temp += 1;
+
+		let mut writer = HashWriter {
+			inner: std::io::BufWriter::new(std::fs::File::create(&path_temp).with_context(
+				|| format!("Failed to open file: {}", path_temp.to_string_lossy()),
+			)?),
+			hasher: sha2::Sha256::new(),
+			size: 0,
+		};
+		if let Err(e) = writer
+			.write_all(head)
+			.and_then(|_| std::io::copy(data, &mut writer))
+			.and_then(|_| writer.flush())
+		{
+			let _ = std::fs::remove_file(&path_temp);
+			return Err(e).with_context(|| {
+				format!("Failed to write to file: {}", path_temp.to_string_lossy())
+			});
+		}
+
+		let hash = crate::key::to_hex(&writer.hasher.finalize()).to_string();
+		let path = self.path.join(&hash[..2]).join(&hash);
+		if path.exists() {
+			std::fs::remove_file(&path_temp).with_context(|| {
+				format!("Failed to delete file: {}", path_temp.to_string_lossy())
+			})?;
+		} else {
+			std::fs::create_dir_all(path.parent().unwrap())
+				.with_context(|| format!("Failed to create path: {}", path.to_string_lossy()))?;
+			std::fs::rename(&path_temp, &path).with_context(|| {
+				format!("Failed to move file to store: {}", path.to_string_lossy())
+			})?;
+		}
+
+		Ok(Object {
+			path,
+			hash,
+			size: writer.size,
+		})
+	}
+}
+
+/// Create `path` as hard link to `object`
+///
+/// If no hard link can be created, e.g. because the store is on another file system, a relative
+/// symbolic link is created instead.
+pub fn link(object: &Object, path: &std::path::Path) -> Result<(), crate::error::Error> {
+	if std::fs::hard_link(&object.path, path).is_ok() {
+		return Ok(());
+	}
+
+	let directory = path
+		.parent()
+		.unwrap_or_else(|| std::path::Path::new("."))
+		.canonicalize()
+		.with_context(|| format!("Failed to resolve path: {}", path.to_string_lossy()))?;
+	let target = object
+		.path
+		.canonicalize()
+		.with_context(|| format!("Failed to resolve path: {}", object.path.to_string_lossy()))?;
+	symlink(&relative_path(&directory, &target), path)
+		.with_context(|| format!("Failed to create link: {}", path.to_string_lossy()))?;
+
+	Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &std::path::Path, path: &std::path::Path) -> std::io::Result<()> {
+	std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &std::path::Path, path: &std::path::Path) -> std::io::Result<()> {
+	std::os::windows::fs::symlink_file(target, path)
+}
+
+/// Path of `target` relative to `directory`, both have to be absolute
+fn relative_path(directory: &std::path::Path, target: &std::path::Path) -> std::path::PathBuf {
+	let common = directory
+		.components()
+		.zip(target.components())
+		.take_while(|(a, b)| a == b)
+		.count();
+
+	let mut path = std::path::PathBuf::new();
+	for _ in directory.components().skip(common) {
+		path.push("..");
+	}
+	path.extend(target.components().skip(common));
+	path
+}
+
+/// Writer computing the hash and size of the written data
+struct HashWriter<W: Write> {
+	inner: W,
+	hasher: sha2::Sha256,
+	size: u64,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		let count = self.inner.write(buf)?;
+		self.hasher.update(&buf[..count]);
+		self.size += count as u64;
+		Ok(count)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn relative() {
+		assert_eq!(
+			relative_path(
+				std::path::Path::new("/a/out/attachment"),
+				std::path::Path::new("/a/store/ab/abc")
+			),
+			std::path::PathBuf::from("../../store/ab/abc")
+		);
+	}
+
+	#[test]
+	fn write() {
+		let dir = crate::temp_dir::TempDir::new("store");
+		let path = dir.path();
+		let mut store = Store::open(path).unwrap();
+
+		let a = store
+			.write(b"he", &mut std::io::Cursor::new(b"llo"))
+			.unwrap();
+		assert_eq!(
+			a.hash,
+			"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+		);
+		assert_eq!(a.size, 5);
+		assert_eq!(std::fs::read(&a.path).unwrap(), b"hello");
+
+		// the same data is stored once
+		let b = store
+			.write(b"", &mut std::io::Cursor::new(b"hello"))
+			.unwrap();
+		assert_eq!(a.path, b.path);
+		assert_eq!(std::fs::read_dir(path).unwrap().count(), 1);
+
+		let path_link = path.join("link");
+		link(&a, &path_link).unwrap();
+		assert_eq!(std::fs::read(&path_link).unwrap(), b"hello");
+	}
+}
@@ -0,0 +1,36 @@
+//! Temporary directories of tests
+
+/// Temporary directory which is removed with its content when dropped
+///
+/// The directory is also removed if a test panics, so failed tests leave nothing behind.
+pub struct TempDir {
+	path: std::path::PathBuf,
+}
+
+impl TempDir {
+	/// Create an empty temporary directory named after the test `name`
+	///
+	/// The name contains the process id, leftovers of earlier processes with the same id are
+	/// removed.
+	pub fn new(name: &str) -> Self {
+		let path = std::env::temp_dir().join(format!(
+			"signal-backup-decode-test-{}-{}",
+			name,
+			std::process::id()
+		));
+		let _ = std::fs::remove_dir_all(&path);
+		std::fs::create_dir_all(&path).unwrap();
+		Self { path }
+	}
+
+	/// Path of the directory
+	pub fn path(&self) -> &std::path::Path {
+		&self.path
+	}
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.path);
+	}
+}